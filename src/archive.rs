@@ -0,0 +1,200 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::error::Error;
+use crate::fileman;
+use crate::paths;
+use crate::store;
+
+use fileman::{App, Apps};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use paths::TempDir;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Component, Path};
+use store::Store;
+use tar::{Archive, Builder, Header};
+
+/// Name of the manifest at the root of an archive.
+pub const MANIFEST_FILE_NAME: &str = "manifest.toml";
+
+// version of the archive layout
+const ARCHIVE_VERSION: u32 = 1;
+
+/// Lists every file in an archive with its SHA-256 hash.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub version: u32,
+    /// Version of setman which created the archive
+    pub setman_version: String,
+    /// Paths relative to the archive root, mapped to hex encoded hashes
+    pub files: BTreeMap<String, String>,
+}
+
+/// A verified archive, unpacked into a temporary directory.
+pub struct UnpackedArchive {
+    pub manifest: Manifest,
+    /// The applications defined in the archive
+    pub apps: Apps,
+    // removed when the archive is dropped
+    dir: TempDir,
+}
+
+/// Writes `apps.toml` and the saved files of all apps to a gzipped tarball at `path`,
+/// along with a manifest of their hashes.
+pub fn export(apps: &Apps, store: &Store, path: &Path) -> Result<Manifest, Error> {
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    // config paths in the home directory are kept relative to it so they work for other users
+    let home = paths::get_absolute_path("");
+    let mut portable_apps = apps.clone();
+    for app in portable_apps.items.iter_mut() {
+        if let Ok(rel_path) = app.config_path.strip_prefix(&home) {
+            app.config_path = rel_path.to_path_buf();
+        }
+    }
+    let applist = toml::to_string(&portable_apps).unwrap();
+    entries.push(("apps.toml".to_string(), applist.into_bytes()));
+    for app in apps.items.iter() {
+        let app_path = store.app_path(&app.name);
+        if !app_path.exists() {
+            continue;
+        }
+        for file in fileman::find_files(&app_path)? {
+            let name = format!("settings/{}/{}", &app.name, file.to_str().unwrap());
+            entries.push((name, fs::read(app_path.join(&file))?));
+        }
+    }
+
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        setman_version: env!("CARGO_PKG_VERSION").to_string(),
+        files: entries
+            .iter()
+            .map(|(name, content)| (name.clone(), hash(content)))
+            .collect(),
+    };
+    let manifest_toml = toml::to_string(&manifest).unwrap();
+    entries.insert(
+        0,
+        (MANIFEST_FILE_NAME.to_string(), manifest_toml.into_bytes()),
+    );
+
+    let tmp_path = fileman::tmp_path_for(path);
+    let result = File::create(&tmp_path)
+        .and_then(|file| {
+            let mut builder = Builder::new(GzEncoder::new(file, Compression::default()));
+            for (name, content) in entries.iter() {
+                let mut header = Header::new_gnu();
+                header.set_size(content.len() as u64);
+                header.set_mode(0o600);
+                header.set_cksum();
+                builder.append_data(&mut header, name, content.as_slice())?;
+            }
+            builder.into_inner()?.finish()?.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result?;
+    Ok(manifest)
+}
+
+/// Unpacks the archive at `path` and checks every file in it against its manifest.
+pub fn unpack(path: &Path) -> Result<UnpackedArchive, Error> {
+    let dir = TempDir::new("setman-archive");
+    fs::create_dir_all(dir.path())?;
+    let (manifest, apps) = unpack_into(path, dir.path())?;
+    Ok(UnpackedArchive {
+        manifest,
+        apps,
+        dir,
+    })
+}
+
+fn unpack_into(path: &Path, unpack_path: &Path) -> Result<(Manifest, Apps), Error> {
+    let mut archive = Archive::new(GzDecoder::new(File::open(path)?));
+    archive.unpack(unpack_path)?;
+
+    let manifest_toml = match fs::read_to_string(unpack_path.join(MANIFEST_FILE_NAME)) {
+        Ok(content) => content,
+        Err(_e) => return Err(invalid(format!("{} is missing", MANIFEST_FILE_NAME))),
+    };
+    let manifest: Manifest = match toml::from_str(&manifest_toml) {
+        Ok(manifest) => manifest,
+        Err(e) => return Err(invalid(format!("{} is invalid: {}", MANIFEST_FILE_NAME, e))),
+    };
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(invalid(format!(
+            "version {} is not supported, the newest supported version is {}",
+            manifest.version, ARCHIVE_VERSION
+        )));
+    }
+
+    for (name, expected) in manifest.files.iter() {
+        let rel_path = Path::new(name);
+        if !rel_path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(invalid(format!("{} is not a valid path", name)));
+        }
+        let content = match fs::read(unpack_path.join(rel_path)) {
+            Ok(content) => content,
+            Err(_e) => return Err(invalid(format!("{} is missing", name))),
+        };
+        if &hash(&content) != expected {
+            return Err(invalid(format!("{} does not match its hash", name)));
+        }
+    }
+    for file in fileman::find_files(unpack_path)? {
+        let name = file.to_str().unwrap();
+        if name != MANIFEST_FILE_NAME && !manifest.files.contains_key(name) {
+            return Err(invalid(format!("{} is not in the manifest", name)));
+        }
+    }
+
+    let mut apps = match fs::read_to_string(unpack_path.join("apps.toml")) {
+        Ok(content) => Apps::parse(&content)?,
+        Err(_e) => return Err(invalid("apps.toml is missing".to_string())),
+    };
+    for app in apps.items.iter_mut() {
+        if app.config_path.is_relative() {
+            app.config_path = paths::get_absolute_path(app.config_path.to_str().unwrap());
+        }
+    }
+    Ok((manifest, apps))
+}
+
+impl UnpackedArchive {
+    /// Adds the archive's apps to `apps`, replacing apps with the same name,
+    /// and copies their saved files into `store`. Returns the imported apps.
+    pub fn import(&self, apps: &mut Apps, store: &Store) -> Result<Vec<App>, Error> {
+        for app in self.apps.items.iter() {
+            let source = self.dir.path().join("settings").join(&app.name);
+            if !source.exists() {
+                continue;
+            }
+            let files: Vec<String> = fileman::find_files(&source)?
+                .iter()
+                .map(|file| file.to_str().unwrap().to_string())
+                .collect();
+            fileman::copy_files(&files, &source, &store.app_path(&app.name))?;
+        }
+        apps.merge_apps(self.apps.items.clone())?;
+        Ok(self.apps.items.clone())
+    }
+}
+
+fn hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidArchive(reason)
+}
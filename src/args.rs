@@ -2,30 +2,68 @@
 
 // SPDX-License-Identifier: BSD-2-Clause
 
-use clap::{App, Arg, ArgMatches, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
 pub fn parse_args() -> ArgMatches<'static> {
+    build_app()
+        .subcommand(
+            SubCommand::with_name("__complete")
+                .about("List candidates for completion scripts")
+                .setting(AppSettings::Hidden)
+                .arg(
+                    Arg::with_name("kind")
+                        .possible_values(&["apps"])
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .get_matches()
+}
+
+// the hidden __complete subcommand is left out here, the completion generators
+// can't handle its name and it is not for users anyway
+pub fn build_app() -> App<'static, 'static> {
     App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .value_name("name")
+                .takes_value(true)
+                .global(true)
+                .help("Profile from config.toml to sync with"),
+        )
         .subcommand(
-            SubCommand::with_name("list")
-                .about("Lists all applications")
+            SubCommand::with_name("init")
+                .about("Set up setman on this machine")
                 .arg(
-                    Arg::with_name("app")
-                        .help("Application(s) to list")
-                        .multiple(true),
+                    Arg::with_name("upstream")
+                        .long("upstream")
+                        .value_name("url")
+                        .takes_value(true)
+                        .help("Upstream settings repository"),
+                )
+                .arg(
+                    Arg::with_name("pull")
+                        .long("pull")
+                        .takes_value(false)
+                        .help("Pull saved settings from remote"),
                 )
-                .subcommand(SubCommand::with_name("verbose").help("Print verbose list"))
                 .arg(
-                    Arg::with_name("regex")
-                        .help("Regex search")
-                        .short("r")
-                        .long("regex")
-                        .takes_value(true),
+                    Arg::with_name("install")
+                        .long("install")
+                        .takes_value(false)
+                        .help("Install all applications"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("Lists all applications")
+                .args(&selection_args("app", "Application(s) to list"))
+                .subcommand(SubCommand::with_name("verbose").help("Print verbose list")),
+        )
         .subcommand(
             SubCommand::with_name("install")
                 .about("Install settings for an application")
@@ -33,11 +71,7 @@ pub fn parse_args() -> ArgMatches<'static> {
                     SubCommand::with_name("app")
                         .help("Application to install settings for")
                         .about("Select an application to install for")
-                        .arg(
-                            Arg::with_name("application")
-                                .help("App to install for")
-                                .takes_value(true),
-                        ),
+                        .args(&selection_args("application", "App(s) to install for")),
                 )
                 .subcommand(
                     SubCommand::with_name("all")
@@ -59,11 +93,7 @@ pub fn parse_args() -> ArgMatches<'static> {
                     SubCommand::with_name("app")
                         .help("Application to install settings for")
                         .about("Select an application to install for")
-                        .arg(
-                            Arg::with_name("application")
-                                .help("App to install for")
-                                .takes_value(true),
-                        ),
+                        .args(&selection_args("application", "App(s) to uninstall for")),
                 )
                 .subcommand(
                     SubCommand::with_name("all")
@@ -84,11 +114,7 @@ pub fn parse_args() -> ArgMatches<'static> {
                     SubCommand::with_name("app")
                         .help("Application to install settings for")
                         .about("Select an application to install for")
-                        .arg(
-                            Arg::with_name("application")
-                                .help("App to install for")
-                                .takes_value(true),
-                        ),
+                        .args(&selection_args("application", "App(s) to save")),
                 )
                 .subcommand(
                     SubCommand::with_name("all")
@@ -110,43 +136,232 @@ pub fn parse_args() -> ArgMatches<'static> {
         )
         .subcommand(
             SubCommand::with_name("sync")
-                .about("Sync settings")
+                .about("Pull and install, then save and push applications in one go")
+                .args(&selection_args(
+                    "app",
+                    "Application(s) to sync, all if none are given",
+                ))
                 .arg(
-                    Arg::with_name("direction")
-                        .help("Chose to sync from or to remote")
+                    Arg::with_name("message")
+                        .short("m")
+                        .long("message")
+                        .takes_value(true)
+                        .default_value("Sync settings")
+                        .help("Commit message for the push"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("new").about("Define a new application"))
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Add files to an application, creating it if needed")
+                .arg(
+                    Arg::with_name("app")
+                        .help("Application to add files to")
+                        .required(true)
                         .index(1),
                 )
-                .subcommand(
-                    SubCommand::with_name("all")
-                        .help("Perform action on all added applications")
-                        .arg(
-                            Arg::with_name("skip")
-                                .long("skip")
-                                .takes_value(true)
-                                .multiple(true)
-                                .help("App to skip"),
-                        ),
+                .arg(
+                    Arg::with_name("path")
+                        .help("Path(s) of the file(s) to add")
+                        .required(true)
+                        .multiple(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("forget")
+                .about("Stop tracking files of an application")
+                .arg(
+                    Arg::with_name("app")
+                        .help("Application to forget files of")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .help("File(s) to forget")
+                        .required(true)
+                        .multiple(true)
+                        .index(2),
                 ),
         )
-        .subcommand(SubCommand::with_name("new").about("Define a new application"))
         .subcommand(
             SubCommand::with_name("remove")
                 .about("Remove a saved application")
-                .arg(Arg::with_name("app").help("Application to remove").index(1)),
+                .args(&selection_args("app", "Application(s) to remove")),
         )
         .subcommand(
             SubCommand::with_name("modify")
                 .about("Modify an application")
-                .arg(Arg::with_name("app").help("Application to modify").index(1)),
+                .arg(Arg::with_name("app").help("Application to modify").index(1))
+                .arg(
+                    Arg::with_name("edit")
+                        .long("edit")
+                        .takes_value(false)
+                        .help("Edit the application's definition in $EDITOR"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("push").about("Push saved settings to remote"))
+        .subcommand(SubCommand::with_name("status").about("Show commits waiting to be pushed"))
+        .subcommand(
+            SubCommand::with_name("remote")
+                .about("Manage the mirrors of the upstream, lists them if no subcommand is given")
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Add a mirror, pushed to along with the upstream")
+                        .arg(
+                            Arg::with_name("name")
+                                .help("Name of the mirror")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("url")
+                                .help("Url of the mirror's git repository")
+                                .required(true)
+                                .index(2),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about(
+                            "Remove a mirror, removing origin makes the first mirror the upstream",
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .help("Name of the remote")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list").about(
+                        "List the upstream and its mirrors in the order they are fetched from",
+                    ),
+                ),
         )
+        .subcommand(SubCommand::with_name("pull").about("Pull saved settings from remote"))
         .subcommand(
-            SubCommand::with_name("push").about("Push saved settings to remote"),
+            SubCommand::with_name("watch")
+                .about("Watch application configs and save them when they change")
+                .arg(
+                    Arg::with_name("push")
+                        .long("push")
+                        .takes_value(false)
+                        .help("Commit and push saved changes"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .value_name("seconds")
+                        .takes_value(true)
+                        .default_value("60")
+                        .help("Seconds without changes to wait before pushing"),
+                ),
         )
         .subcommand(
-            SubCommand::with_name("pull").about("Pull saved settings from remote"),
+            SubCommand::with_name("diff")
+                .about("Show how installed settings differ from saved ones")
+                .args(&selection_args("app", "Application(s) to compare")),
         )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Restore an application's settings from a previous commit")
+                .arg(
+                    Arg::with_name("app")
+                        .help("Application to restore")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("at")
+                        .long("at")
+                        .value_name("commit|tag|date")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Revision or date (YYYY-MM-DD [HH:MM], UTC) to restore from"),
+                )
+                .arg(
+                    Arg::with_name("install")
+                        .long("install")
+                        .takes_value(false)
+                        .help("Install the restored settings"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import applications from an existing dotfiles directory or repository")
+                .arg(
+                    Arg::with_name("source")
+                        .help("Path or git url of the dotfiles, or the path of a bare dotfiles repository")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export all applications and their saved settings to an archive")
+                .arg(
+                    Arg::with_name("file")
+                        .help("Archive to write, e.g. settings.tar.gz")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import-archive")
+                .about("Verify and import an archive created by export")
+                .arg(
+                    Arg::with_name("file")
+                        .help("Archive to import")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("install")
+                        .long("install")
+                        .value_name("app")
+                        .takes_value(true)
+                        .multiple(true)
+                        .min_values(0)
+                        .help("Install the given applications, or choose them if none are given"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("doctor").about("Diagnose problems with the setup"))
         .subcommand(
             SubCommand::with_name("compare").about("Checks if upstream is ahead of local save"),
         )
-        .get_matches()
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Generate a shell completion script")
+                .arg(
+                    Arg::with_name("shell")
+                        .help("Shell to generate the script for")
+                        .possible_values(&["bash", "zsh", "fish"])
+                        .required(true)
+                        .index(1),
+                ),
+        )
+}
+
+// the ways to select the applications a command acts on: by name, by regex or by tag
+fn selection_args(name: &'static str, help: &'static str) -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name(name)
+            .help(help)
+            .multiple(true)
+            .conflicts_with_all(&["regex", "tag"]),
+        Arg::with_name("regex")
+            .help("Regex search")
+            .short("r")
+            .long("regex")
+            .takes_value(true)
+            .conflicts_with("tag"),
+        Arg::with_name("tag")
+            .help("Select applications by tag")
+            .short("t")
+            .long("tag")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+    ]
 }
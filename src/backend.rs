@@ -0,0 +1,527 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::config::{Remote, UPSTREAM_REMOTE};
+use crate::error::Error;
+use crate::fileman;
+use crate::gitman;
+use crate::paths::Paths;
+use crate::signing::{self, AllowedSigners};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use git2::{Commit, ErrorCode, Oid, Repository};
+use gitman::GitRepo;
+use std::fs::{self, File};
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+use tar::{Archive, Builder};
+use uuid::Uuid;
+
+/// Holds the revision of the settings published by backends without a history.
+pub const REVISION_FILE_NAME: &str = ".setman-revision";
+
+/// A published state of the settings.
+#[derive(Debug)]
+pub struct Revision {
+    pub id: String,
+    pub summary: String,
+}
+
+/// What `SyncBackend::deliver` did.
+#[derive(Debug, Default)]
+pub struct Delivery {
+    /// The delivered revision, None if there was nothing to deliver
+    pub revision: Option<String>,
+    /// Revisions published elsewhere in the meantime, which the delivered ones were
+    /// rebased onto. Newest first
+    pub integrated: Vec<Revision>,
+    /// Why some remotes did not get the revisions, they stay pending for them
+    pub deferred: Option<Error>,
+}
+
+/// How often a push rejected because the target moved on is retried
+const MAX_PUSH_ATTEMPTS: usize = 3;
+
+/// Somewhere settings are pushed to and pulled from.
+///
+/// Backends work on a working copy in a directory given by the sync engine,
+/// which copies the store into it before publishing and out of it after fetching.
+pub trait SyncBackend {
+    /// Where the settings are synced to, for the user to see
+    fn location(&self) -> String;
+
+    /// Checks that the target can be reached.
+    fn check(&self) -> Result<(), Error>;
+
+    /// Fills `work_path` with the published settings and returns their revision,
+    /// None if nothing has been published yet.
+    fn fetch(&self, work_path: &Path) -> Result<Option<String>, Error>;
+
+    /// Publishes the settings in `work_path`, as filled by `fetch`, and returns the new revision.
+    fn publish(&self, work_path: &Path, message: &str) -> Result<String, Error>;
+
+    /// Finds the revision `at` refers to in the history fetched into `work_path`.
+    fn find_revision(&self, _work_path: &Path, _at: &str) -> Result<Revision, Error> {
+        Err(no_history(&self.location()))
+    }
+
+    /// Writes the files of `dir_name` as they were in `revision` to `dest`.
+    /// Returns the names of the restored files.
+    fn restore_dir(
+        &self,
+        _work_path: &Path,
+        _revision: &str,
+        _dir_name: &str,
+        _dest: &Path,
+    ) -> Result<Vec<String>, Error> {
+        Err(no_history(&self.location()))
+    }
+
+    /// Fills `work_path` from a local copy of the published settings without reaching
+    /// the target, so settings can be published while it is unreachable. Returns false
+    /// for backends without a local copy.
+    fn checkout(&self, _work_path: &Path) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    /// Delivers the revisions which were published locally, integrating revisions
+    /// published elsewhere in the meantime.
+    fn deliver(&self) -> Result<Delivery, Error> {
+        Ok(Delivery::default())
+    }
+
+    /// Revisions which have been published locally but not delivered yet, newest first.
+    fn pending(&self) -> Result<Vec<Revision>, Error> {
+        Ok(vec![])
+    }
+
+    /// Makes sure `revision` and the revisions published since `since` are signed
+    /// with one of the allowed keys. Backends without signatures refuse all revisions.
+    fn verify(
+        &self,
+        revision: &str,
+        _since: Option<&str>,
+        _signers: &AllowedSigners,
+    ) -> Result<(), Error> {
+        Err(Error::Unverified(
+            revision.to_string(),
+            format!("{} has no signatures", self.location()),
+        ))
+    }
+}
+
+/// A git repository. It is cloned once into setman's directory, fetching updates
+/// that clone and publishing commits to it, so commits made offline are pushed
+/// once the upstream can be reached again. Mirrors share the clone, see `with_mirrors`.
+pub struct GitBackend {
+    // the upstream followed by its mirrors
+    remotes: Vec<Remote>,
+    repo_path: PathBuf,
+    signers: Option<AllowedSigners>,
+}
+
+/// A directory such as a mounted drive or a network share, mirrored like rsync would.
+/// As files which are not published are deleted, the first publish needs an empty
+/// directory, after that the revision file marks it as setman's.
+pub struct DirectoryBackend {
+    path: PathBuf,
+}
+
+/// A gzipped tarball holding all settings.
+pub struct TarballBackend {
+    path: PathBuf,
+}
+
+impl GitBackend {
+    /// Syncs with the repository at `url` through the clone named `clone_name`. The
+    /// clone outlives changes to the url, commits waiting in it are pushed to the new one.
+    pub fn new(url: &str, clone_name: &str) -> GitBackend {
+        let repo_path = Paths::default().repos_path.join(clone_name);
+        let upstream = Remote {
+            name: UPSTREAM_REMOTE.to_string(),
+            url: url.to_string(),
+        };
+        GitBackend {
+            remotes: vec![upstream],
+            repo_path,
+            signers: None,
+        }
+    }
+
+    /// Pushes to `mirrors` along with the upstream, they are force pushed to once
+    /// the first reachable remote took the push. Fetches from the first reachable
+    /// remote, in order.
+    pub fn with_mirrors(mut self, mirrors: &[Remote]) -> GitBackend {
+        self.remotes.extend(mirrors.iter().cloned());
+        self
+    }
+
+    /// Refuses fetched commits which are not signed by one of `signers`, see
+    /// `GitRepo::verifying`.
+    pub fn with_signers(mut self, signers: Option<AllowedSigners>) -> GitBackend {
+        self.signers = signers;
+        self
+    }
+
+    fn repo(&self) -> Result<GitRepo, Error> {
+        self.repo_for(&self.remotes[0])
+    }
+
+    fn repo_for(&self, remote: &Remote) -> Result<GitRepo, Error> {
+        Ok(GitRepo::with_remote(
+            remote.name.clone(),
+            remote.url.clone(),
+            self.repo_path.clone(),
+        )?
+        .verifying(self.signers.clone()))
+    }
+
+    // the local clone, None if the upstream has not been cloned yet
+    fn local_repo(&self) -> Option<Repository> {
+        Repository::open(&self.repo_path).ok()
+    }
+
+    // runs `f` for each remote until one can be reached, returns the first
+    // remote's error if none can. Other errors are returned right away
+    fn first_reachable<T>(
+        &self,
+        mut f: impl FnMut(&GitRepo) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut first_error = None;
+        for remote in self.remotes.iter() {
+            match f(&self.repo_for(remote)?) {
+                Err(e) if e.is_network() => {
+                    if first_error.is_none() {
+                        first_error = Some(match e {
+                            Error::Git(e) => self.remote_error(remote, e),
+                            e => e,
+                        });
+                    }
+                }
+                result => return result,
+            }
+        }
+        Err(first_error.unwrap())
+    }
+
+    // pushes to `git_repo`'s upstream, integrating its commits if it moved on
+    fn push_rebasing(
+        &self,
+        git_repo: &GitRepo,
+        repo: &Repository,
+        integrated: &mut Vec<Revision>,
+    ) -> Result<Result<(), git2::Error>, Error> {
+        let mut attempts = 1;
+        loop {
+            match git_repo.push_commits(repo) {
+                // another machine pushed first, rebase onto its commits and try again
+                Err(e) if e.code() == ErrorCode::NotFastForward && attempts < MAX_PUSH_ATTEMPTS => {
+                    let commits = git_repo.fetch_upstream(repo)?;
+                    integrated.extend(commits.iter().map(revision_of));
+                    attempts += 1;
+                }
+                result => return Ok(result),
+            }
+        }
+    }
+
+    // names the remote an error came from if there are several
+    fn remote_error(&self, remote: &Remote, e: git2::Error) -> Error {
+        match self.remotes.len() {
+            1 => e.into(),
+            _ => Error::Remote(remote.name.clone(), e),
+        }
+    }
+}
+
+impl SyncBackend for GitBackend {
+    fn location(&self) -> String {
+        let upstream = &self.remotes[0].url;
+        let mirrors: Vec<&str> = self.remotes[1..]
+            .iter()
+            .map(|remote| remote.name.as_str())
+            .collect();
+        match mirrors.is_empty() {
+            true => upstream.clone(),
+            false => format!("{} (mirrored to {})", upstream, mirrors.join(", ")),
+        }
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        self.first_reachable(|git_repo| Ok(git_repo.check_remote()?))
+    }
+
+    fn fetch(&self, work_path: &Path) -> Result<Option<String>, Error> {
+        let upstream = self.first_reachable(|git_repo| {
+            let repo = git_repo.open_or_clone()?;
+            git_repo.fetch_upstream(&repo)?;
+            let upstream = repo.refname_to_id(&git_repo.upstream_branch());
+            Ok(upstream.ok())
+        })?;
+        self.checkout(work_path)?;
+        Ok(upstream.map(|upstream| upstream.to_string()))
+    }
+
+    fn publish(&self, work_path: &Path, message: &str) -> Result<String, Error> {
+        let git_repo = self.repo()?;
+        let repo = git_repo.open_or_clone()?;
+        mirror_tree(work_path, &self.repo_path)?;
+        let commit_id = git_repo.commit_changes(&repo, message)?;
+        Ok(commit_id.to_string())
+    }
+
+    fn find_revision(&self, _work_path: &Path, at: &str) -> Result<Revision, Error> {
+        let git_repo = self.repo()?;
+        let repo = git_repo.open_or_clone()?;
+        let commit = git_repo.find_commit(&repo, at)?;
+        Ok(revision_of(&commit))
+    }
+
+    fn restore_dir(
+        &self,
+        _work_path: &Path,
+        revision: &str,
+        dir_name: &str,
+        dest: &Path,
+    ) -> Result<Vec<String>, Error> {
+        let git_repo = self.repo()?;
+        let repo = git_repo.open_or_clone()?;
+        let commit = repo.find_commit(Oid::from_str(revision)?)?;
+        git_repo.restore_dir(&repo, &commit, dir_name, dest)
+    }
+
+    fn checkout(&self, work_path: &Path) -> Result<bool, Error> {
+        if self.local_repo().is_none() {
+            return Ok(false);
+        }
+        fs::create_dir_all(work_path)?;
+        copy_tree(&self.repo_path, work_path)?;
+        Ok(true)
+    }
+
+    fn deliver(&self) -> Result<Delivery, Error> {
+        let repo = match self.local_repo() {
+            Some(repo) => repo,
+            None => return Ok(Delivery::default()),
+        };
+        let mut delivery = Delivery::default();
+        let mut failed: Vec<Error> = Vec::new();
+        let mut delivered = false;
+        for remote in self.remotes.iter() {
+            let git_repo = self.repo_for(remote)?;
+            let result = match delivered {
+                // the remotes after the first one which took the push mirror it
+                true => git_repo.mirror_commits(&repo),
+                false => self.push_rebasing(&git_repo, &repo, &mut delivery.integrated)?,
+            };
+            match result {
+                Ok(()) => delivered = true,
+                Err(e) => failed.push(self.remote_error(remote, e)),
+            }
+        }
+        if !delivered {
+            return Err(failed.remove(0));
+        }
+        delivery.deferred = failed.into_iter().next();
+        delivery.revision = Some(self.repo()?.get_parent_commit(&repo)?.id().to_string());
+        Ok(delivery)
+    }
+
+    fn pending(&self) -> Result<Vec<Revision>, Error> {
+        let repo = match self.local_repo() {
+            Some(repo) => repo,
+            None => return Ok(vec![]),
+        };
+        // commits missing from any of the remotes
+        let mut pending: Vec<Commit> = Vec::new();
+        for remote in self.remotes.iter() {
+            for commit in self.repo_for(remote)?.pending_commits(&repo)? {
+                if !pending.iter().any(|pending| pending.id() == commit.id()) {
+                    pending.push(commit);
+                }
+            }
+        }
+        pending.sort_by_key(|commit| std::cmp::Reverse(commit.time().seconds()));
+        Ok(pending.iter().map(revision_of).collect())
+    }
+
+    fn verify(
+        &self,
+        revision: &str,
+        since: Option<&str>,
+        signers: &AllowedSigners,
+    ) -> Result<(), Error> {
+        let repo = self.repo()?.open_or_clone()?;
+        let head = Oid::from_str(revision)?;
+        // without a known last sync only the head can be checked
+        let since = match since.and_then(|since| Oid::from_str(since).ok()) {
+            Some(since) if repo.find_commit(since).is_ok() => since,
+            _ => return signing::verify_commit(&repo, head, signers),
+        };
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(head)?;
+        revwalk.hide(since)?;
+        for oid in revwalk {
+            signing::verify_commit(&repo, oid?, signers)?;
+        }
+        Ok(())
+    }
+}
+
+impl DirectoryBackend {
+    pub fn new(path: PathBuf) -> DirectoryBackend {
+        DirectoryBackend { path }
+    }
+}
+
+impl SyncBackend for DirectoryBackend {
+    fn location(&self) -> String {
+        format!("{:?}", self.path)
+    }
+
+    // the directory has to exist, an unmounted drive must not look like an empty target
+    fn check(&self) -> Result<(), Error> {
+        match self.path.is_dir() {
+            true => Ok(()),
+            false => Err(Error::Missing(self.path.clone())),
+        }
+    }
+
+    fn fetch(&self, work_path: &Path) -> Result<Option<String>, Error> {
+        self.check()?;
+        fs::create_dir_all(work_path)?;
+        copy_tree(&self.path, work_path)?;
+        Ok(read_revision(work_path)?)
+    }
+
+    fn publish(&self, work_path: &Path, message: &str) -> Result<String, Error> {
+        self.check()?;
+        let published = self.path.join(REVISION_FILE_NAME).exists();
+        if !published && self.path.read_dir()?.next().is_some() {
+            return Err(Error::ForeignDirectory(self.path.clone()));
+        }
+        let revision = write_revision(work_path, message)?;
+        mirror_tree(work_path, &self.path)?;
+        Ok(revision)
+    }
+}
+
+impl TarballBackend {
+    pub fn new(path: PathBuf) -> TarballBackend {
+        TarballBackend { path }
+    }
+}
+
+impl SyncBackend for TarballBackend {
+    fn location(&self) -> String {
+        format!("{:?}", self.path)
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        match self.path.parent() {
+            Some(dir) if dir.is_dir() => Ok(()),
+            Some(dir) => Err(Error::Missing(dir.to_path_buf())),
+            None => Err(Error::Missing(self.path.clone())),
+        }
+    }
+
+    fn fetch(&self, work_path: &Path) -> Result<Option<String>, Error> {
+        self.check()?;
+        fs::create_dir_all(work_path)?;
+        // nothing has been exported yet
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let mut archive = Archive::new(GzDecoder::new(File::open(&self.path)?));
+        archive.unpack(work_path)?;
+        Ok(read_revision(work_path)?)
+    }
+
+    fn publish(&self, work_path: &Path, message: &str) -> Result<String, Error> {
+        self.check()?;
+        let revision = write_revision(work_path, message)?;
+        let tmp_path = fileman::tmp_path_for(&self.path);
+        let result = File::create(&tmp_path)
+            .and_then(|file| {
+                let mut builder = Builder::new(GzEncoder::new(file, Compression::default()));
+                builder.append_dir_all(".", work_path)?;
+                builder.into_inner()?.finish()?.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &self.path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result?;
+        Ok(revision)
+    }
+}
+
+fn revision_of(commit: &Commit) -> Revision {
+    Revision {
+        id: commit.id().to_string(),
+        summary: commit.summary().unwrap_or_default().to_string(),
+    }
+}
+
+fn no_history(location: &str) -> Error {
+    Error::Unsupported(format!("{} keeps no history", location))
+}
+
+// copies every file below `source` to `dest`, returns the copied files relative to `source`
+pub(crate) fn copy_tree(source: &Path, dest: &Path) -> IOResult<Vec<PathBuf>> {
+    let files = fileman::find_files(source)?;
+    for file in files.iter() {
+        let dest_path = dest.join(file);
+        if let Some(dir) = dest_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fileman::copy_atomic(&source.join(file), &dest_path)?;
+    }
+    Ok(files)
+}
+
+// makes `dest` hold the same files as `source`, `.git` directories are left alone
+fn mirror_tree(source: &Path, dest: &Path) -> IOResult<()> {
+    let files = copy_tree(source, dest)?;
+    // drop files which are gone from the source, and the directories that empties
+    for file in fileman::find_files(dest)? {
+        if !files.contains(&file) {
+            fs::remove_file(dest.join(&file))?;
+        }
+    }
+    remove_empty_dirs(dest)
+}
+
+// removes the empty directories below `dir`
+fn remove_empty_dirs(dir: &Path) -> IOResult<()> {
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        if entry.file_name() == ".git" || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        remove_empty_dirs(&entry.path())?;
+        if entry.path().read_dir()?.next().is_none() {
+            fs::remove_dir(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+// gives the settings in `work_path` a new revision
+fn write_revision(work_path: &Path, message: &str) -> IOResult<String> {
+    let revision = Uuid::new_v4().to_string();
+    let content = format!("{}\n{}\n", revision, message.trim());
+    fileman::write_atomic(&work_path.join(REVISION_FILE_NAME), content)?;
+    Ok(revision)
+}
+
+fn read_revision(work_path: &Path) -> IOResult<Option<String>> {
+    match fs::read_to_string(work_path.join(REVISION_FILE_NAME)) {
+        Ok(content) => Ok(content.lines().next().map(String::from)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,1042 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::readline;
+use crate::thiserror;
+
+use colored::*;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use setman::archive;
+use setman::config::{Config, UPSTREAM_REMOTE};
+use setman::dotfiles;
+use setman::fileman::{self, App, Apps, TOMLError, SCHEMA_VERSION};
+use setman::hooks::HookOutcome;
+use setman::lock::SetManLock;
+use setman::paths::{self, Paths};
+use setman::store::{FileDiff, Store};
+use setman::sync::PushReport;
+use setman::{Error, Report, SyncEngine};
+use std::fmt::Display;
+use std::io::Error as IOError;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use std::{fs, path::Path, path::PathBuf};
+use thiserror::Error;
+
+pub enum SetManAction<'a> {
+    Install(AppSelection<'a>),
+    Uninstall(AppSelection<'a>),
+    Save(AppSelection<'a>),
+    Modify(&'a str),
+    Edit(&'a str),
+    Remove(AppSelection<'a>),
+    New,
+    Add(&'a str, &'a [String]),
+    Forget(&'a str, &'a [String]),
+    Push,
+    Pull,
+}
+
+/// The applications an action applies to.
+pub enum AppSelection<'a> {
+    /// Apps by name, picked interactively if no names are given
+    Names(Vec<String>),
+    Regex(&'a str),
+    /// Apps with any of the tags
+    Tags(Vec<String>),
+    /// Every app except the skipped ones
+    All(Vec<String>),
+}
+
+#[derive(Error, Debug)]
+pub enum SetManError {
+    #[error("Invalid option")]
+    InvalidOption,
+    #[error("Invalid application: {0}")]
+    InvalidApp(String),
+    #[error("{0:?} is not a file")]
+    NotAFile(PathBuf),
+    #[error("{0:?} is not in {1:?}, the config path of {2}")]
+    ConfigPathMismatch(PathBuf, PathBuf, String),
+}
+
+pub fn sync_settings(action: SetManAction, profile: Option<&str>) -> Result<(), SetManError> {
+    match action {
+        SetManAction::Push => {
+            push_settings(None, profile);
+            Ok(())
+        }
+        SetManAction::Pull => {
+            let apps = load_apps();
+            let engine = sync_engine(profile);
+            job!("Pulling from {}", engine.backend().location());
+            for report in check(engine.pull(&apps)) {
+                job!("Updating {}", &report.app);
+                print_report(&report, "Pulled");
+            }
+            info!("Done");
+            Ok(())
+        }
+        _ => Err(SetManError::InvalidOption),
+    }
+}
+
+/// Pulls and installs, then saves and pushes the selected apps, printing a summary.
+pub fn sync(selection: AppSelection, commit_msg: &str, profile: Option<&str>) {
+    let apps = load_apps();
+    let selected = select_apps(&apps, selection);
+    let engine = sync_engine(profile);
+    job!("Syncing with {}", engine.backend().location());
+    let report = match engine.sync(&apps, &selected, commit_msg) {
+        Ok(report) => report,
+        Err(e @ Error::RestoreFailed(..)) => {
+            warn!("Sync failed: {}", e);
+            std::process::exit(1)
+        }
+        Err(e) => {
+            warn!("Sync failed, local changes were rolled back: {}", e);
+            std::process::exit(1)
+        }
+    };
+
+    for pulled in report.pulled.iter() {
+        job!("Updating {}", &pulled.app);
+        print_report(pulled, "Pulled");
+    }
+    for saved in report.saved.iter() {
+        job!("Saving {}", &saved.app);
+        print_report(saved, "Saved");
+    }
+    for installed in report.installed.iter() {
+        job!("Installing {}", &installed.app);
+        print_report(installed, "Installed");
+    }
+    for conflict in report.conflicts.iter() {
+        warn!(
+            "{} changed locally and in {}: {}",
+            &conflict.app,
+            engine.backend().location(),
+            conflict.files.join(", ")
+        );
+        warn!(
+            "Compare with `setman diff {}`, then save or install it",
+            &conflict.app
+        );
+    }
+    match &report.pushed {
+        Some(pushed) => print_push_report(pushed, &engine),
+        None => info!("Nothing to push"),
+    }
+    info!(
+        "{} pulled, {} saved, {} installed, {} in conflict",
+        report.pulled.len(),
+        report.saved.len(),
+        report.installed.len(),
+        report.conflicts.len()
+    );
+}
+
+// pushes the local settings, prompting for a commit message if none is given
+fn push_settings(commit_msg: Option<&str>, profile: Option<&str>) {
+    let apps = load_apps();
+    let engine = sync_engine(profile);
+    let commit_msg = match commit_msg {
+        Some(msg) => msg.to_string(),
+        None => check(readline::read("Enter a commit message")),
+    };
+    job!("Pushing to {}", engine.backend().location());
+    print_push_report(&check(engine.push(&apps, &commit_msg)), &engine);
+    info!("Done!");
+}
+
+fn print_push_report(report: &PushReport, engine: &SyncEngine) {
+    for stored in report.stored.iter() {
+        info!(
+            "Moved {} of {} to the side store as {}",
+            stored.file, stored.app, stored.oid
+        );
+    }
+    for revision in report.integrated.iter() {
+        info!("Rebased onto {} {}", &revision.id[..7], revision.summary);
+    }
+    if !report.integrated.is_empty() {
+        info!("Run `setman pull` to bring in the integrated changes");
+    }
+    match &report.deferred {
+        Some(e) => {
+            warn!("Could not push to {}: {}", engine.backend().location(), e);
+            info!(
+                "Kept {} locally, {} commit(s) are waiting to be pushed, see `setman status`",
+                report.revision,
+                report.pending.len()
+            );
+        }
+        None => info!("Published revision {}", report.revision),
+    }
+}
+
+/// Shows the commits which are waiting to be pushed.
+pub fn status(profile: Option<&str>) {
+    let engine = sync_engine(profile);
+    job!("Status of {}", engine.backend().location());
+    let pending = check(engine.pending());
+    if pending.is_empty() {
+        info!("Nothing waiting to be pushed");
+        return;
+    }
+    info!("{} commit(s) waiting to be pushed:", pending.len());
+    for revision in pending.iter() {
+        println!(
+            "{}{} {}",
+            " ".repeat(4),
+            &revision.id[..7].yellow(),
+            revision.summary
+        );
+    }
+}
+
+/// Lists the upstream and its mirrors, in the order they are fetched from.
+pub fn list_remotes() {
+    let config = check(Config::load());
+    for remote in check(config.upstream_remotes()).iter() {
+        println!("{}{} {}", " ".repeat(4), remote.name.yellow(), remote.url);
+    }
+}
+
+/// Adds a mirror of the upstream.
+pub fn add_remote(name: &str, url: &str) {
+    let mut config = check(Config::load());
+    check(config.add_remote(name, url));
+    info!("Added mirror {}, the next push or sync pushes to it", name);
+}
+
+/// Removes a remote, the first mirror takes over if it is the upstream.
+pub fn remove_remote(name: &str) {
+    let mut config = check(Config::load());
+    let pending = pending_upstream_commits();
+    let removed = check(config.remove_remote(name));
+    info!("Removed {} {}", removed.name, removed.url);
+    if removed.name == UPSTREAM_REMOTE {
+        let upstream = &check(config.upstream_remotes())[0];
+        info!("The upstream is {} now", upstream.url);
+        if pending > 0 {
+            warn!(
+                "{} commit(s) waiting to be pushed go to {} now",
+                pending, upstream.url
+            );
+        }
+    }
+}
+
+// how many commits are waiting to be pushed to the upstream set with `setman init`
+fn pending_upstream_commits() -> usize {
+    Config::load()
+        .and_then(|config| config.upstream_profile())
+        .and_then(|profile| profile.backend(None, None).pending())
+        .map(|pending| pending.len())
+        .unwrap_or(0)
+}
+
+// loads the application list, exiting with a hint if it is missing or broken
+fn load_apps() -> Apps {
+    let applist_path = Paths::default().applist_path;
+    match Apps::load() {
+        Ok(apps) => apps,
+        Err(TOMLError::FileError { source }) => fail(format!(
+            "Failed to read from file {:?}: {}, run `setman init` to set up setman",
+            applist_path, source
+        )),
+        Err(e) => fail(format!("{:?}: {}", applist_path, e)),
+    }
+}
+
+/// Writes the application list back in the current schema if an older setman wrote it.
+/// Errors loading the list are left to the command to report.
+pub fn migrate_apps(lock: &SetManLock) {
+    let apps = match Apps::load() {
+        Ok(apps) => apps,
+        Err(_e) => return,
+    };
+    if let Some(migration) = &apps.migration {
+        check(apps.save_migration(lock));
+        info!(
+            "Migrated {:?} from version {} to {}, the old file was saved as {:?}",
+            Paths::default().applist_path,
+            migration.from_version,
+            SCHEMA_VERSION,
+            migration.backup_path
+        );
+    }
+}
+
+// prompts for the upstream url if no profile is given and none is set
+fn sync_engine(profile: Option<&str>) -> SyncEngine {
+    match SyncEngine::for_profile(profile) {
+        Ok(engine) => engine,
+        Err(Error::NoUpstream) => {
+            let url = check(readline::read("Enter your repo's upstream url"));
+            check(fileman::write_atomic(&Paths::default().upstream_path, &url));
+            check(SyncEngine::for_profile(profile))
+        }
+        Err(e) => fail(e),
+    }
+}
+
+// unwraps `result`, exiting with the error's message
+pub fn check<T, E: Display>(result: Result<T, E>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => fail(e),
+    }
+}
+
+// prints `message` and exits
+pub fn fail(message: impl Display) -> ! {
+    warn!("{}", message);
+    std::process::exit(1)
+}
+
+// prints the hooks, warnings and files of an action on an app
+fn print_report(report: &Report, verb: &str) {
+    for (event, outcome) in report.hooks.iter() {
+        match outcome {
+            HookOutcome::Succeeded => info!("Ran {} hook for {}", event.name(), &report.app),
+            HookOutcome::Failed(e) => warn!("{}", e),
+            HookOutcome::Ignored(_e) => {}
+            HookOutcome::Skipped(e) => warn!("{}, skipping {}", e, &report.app),
+        }
+    }
+    for warning in report.warnings.iter() {
+        warn!("{}", warning);
+    }
+    for file in report.files.iter() {
+        info!("{} {}", verb, file.bold());
+    }
+}
+
+pub fn init(upstream_url: Option<&str>, pull: bool, install: bool, profile: Option<&str>) {
+    let paths = Paths::default();
+
+    for dir in [&paths.setman_path, &paths.settings_path].iter() {
+        if dir.exists() {
+            info!("{:?} already exists", dir);
+            continue;
+        }
+        check(fs::create_dir_all(dir));
+        info!("Created {:?}", dir);
+    }
+    let _lock = match SetManLock::acquire() {
+        Ok(lock) => lock,
+        Err(e) => fail(e),
+    };
+
+    if paths.applist_path.exists() {
+        info!("{:?} already exists", &paths.applist_path);
+    } else {
+        let toml = toml::to_string(&Apps::empty()).unwrap();
+        check(fileman::write_atomic(&paths.applist_path, toml));
+        info!("Created {:?}", &paths.applist_path);
+    }
+
+    if let Some(url) = upstream_url {
+        let current_url = fs::read_to_string(&paths.upstream_path).ok();
+        match current_url {
+            Some(current) if current.trim() == url => {
+                info!("Upstream is already set to {}", url)
+            }
+            Some(current) => {
+                let pending = pending_upstream_commits();
+                if pending > 0 {
+                    warn!(
+                        "{} commit(s) are waiting to be pushed to {}, they go to {} if you replace it",
+                        pending,
+                        current.trim(),
+                        url
+                    );
+                }
+                if check(readline::are_you_sure(format!(
+                    "replace upstream {} with {}",
+                    current.trim(),
+                    url
+                ))) {
+                    check(fileman::write_atomic(&paths.upstream_path, url));
+                    info!("Upstream set to {}", url);
+                }
+            }
+            None => {
+                check(fileman::write_atomic(&paths.upstream_path, url));
+                info!("Upstream set to {}", url);
+            }
+        }
+    }
+
+    let engine = sync_engine(profile);
+    job!("Checking {}", engine.backend().location());
+    if let Err(e) = engine.check_remote() {
+        warn!(
+            "Upstream is not reachable: {}. Fix it with `setman init --upstream <url>`",
+            e
+        );
+        return;
+    }
+    info!("Upstream is reachable");
+
+    if pull {
+        check(sync_settings(SetManAction::Pull, profile));
+    }
+    if install {
+        job!("Installing all applications");
+        app_action(SetManAction::Install(AppSelection::All(vec![])));
+    }
+    info!("Done");
+}
+
+pub fn print_app_list(selection: AppSelection, verbose: bool) {
+    job!("Applications:");
+
+    let apps = load_apps();
+    for app in select_apps(&apps, selection) {
+        print_app!(app, verbose);
+    }
+}
+
+// resolves `selection` to the apps it names, in the order of the app list
+// unless they were named explicitly
+fn select_apps(apps: &Apps, selection: AppSelection) -> Vec<App> {
+    let selected: Vec<App> = match selection {
+        AppSelection::Names(names) => {
+            let names = match names.is_empty() {
+                true => pick_apps(true),
+                false => names,
+            };
+            return names
+                .iter()
+                .map(|name| check(apps.find_app_by_name(name)))
+                .collect();
+        }
+        AppSelection::Regex(regex) => check(apps.find_apps_from_regex(regex))
+            .into_iter()
+            .cloned()
+            .collect(),
+        AppSelection::Tags(tags) => apps.find_apps_by_tags(&tags).into_iter().cloned().collect(),
+        AppSelection::All(skip) => apps
+            .items
+            .iter()
+            .filter(|app| !skip.contains(&app.name))
+            .cloned()
+            .collect(),
+    };
+    if selected.is_empty() {
+        warn!("No application matches");
+    }
+    selected
+}
+
+/// Lets the user pick applications with a fuzzy filter, for when none were named.
+pub fn pick_apps(multiple: bool) -> Vec<String> {
+    let apps = load_apps();
+    if apps.items.is_empty() {
+        fail("There are no applications to pick from, add one with `setman new`");
+    }
+    let names: Vec<&str> = apps.items.iter().map(|app| app.name.as_str()).collect();
+    let picked = check(readline::fuzzy_select(names.clone(), multiple));
+    if picked.is_empty() {
+        info!("No application selected");
+    }
+    picked.into_iter().map(|i| names[i].to_string()).collect()
+}
+
+fn install_app(app: &App) {
+    job!("Installing {}", &app.name);
+    print_report(&check(Store::default().install(app)), "Installed");
+}
+
+// lists the files uninstalling `apps` would remove and asks to go on
+fn confirm_uninstall(apps: &[App]) -> bool {
+    let store = Store::default();
+    let mut count = 0;
+    for app in apps {
+        let files = check(store.uninstall_preview(app));
+        if files.is_empty() {
+            info!("{} has no installed files", &app.name);
+            continue;
+        }
+        info!("Uninstalling {} removes:", &app.name);
+        for file in files.iter() {
+            println!("{}{}", " ".repeat(4), file.display());
+        }
+        count += files.len();
+    }
+    count > 0 && check(readline::are_you_sure(format!("remove {} file(s)", count)))
+}
+
+fn uninstall_app(app: &App) {
+    job!("Uninstalling {}", &app.name);
+    print_report(&check(Store::default().uninstall(app)), "Removed");
+}
+
+fn save_app(app: &App) {
+    job!("Saving {}", &app.name);
+    print_report(&check(Store::default().save(app)), "Saved");
+}
+
+pub fn app_action(action: SetManAction) {
+    let mut apps = load_apps();
+    match action {
+        SetManAction::Install(selection) => {
+            for app in select_apps(&apps, selection) {
+                install_app(&app);
+            }
+        }
+        SetManAction::Uninstall(selection) => {
+            let selected = select_apps(&apps, selection);
+            if confirm_uninstall(&selected) {
+                for app in selected.iter() {
+                    uninstall_app(app);
+                }
+            }
+        }
+        SetManAction::Save(selection) => {
+            for app in select_apps(&apps, selection) {
+                save_app(&app);
+            }
+        }
+        SetManAction::Modify(app_name) => {
+            job!("Modify {}", &app_name);
+            check(modify_application(&mut apps, app_name, false));
+        }
+        SetManAction::Edit(app_name) => {
+            job!("Edit {}", &app_name);
+            check(modify_application(&mut apps, app_name, true));
+        }
+        SetManAction::Remove(selection) => {
+            let selected = select_apps(&apps, selection);
+            if selected.is_empty() {
+                return;
+            }
+            let names: Vec<&str> = selected.iter().map(|app| app.name.as_str()).collect();
+            if !check(readline::are_you_sure(format!(
+                "remove {}",
+                names.join(", ")
+            ))) {
+                return;
+            }
+            for app_name in names {
+                job!("Removing {}", app_name);
+                // remove app from saved list of apps
+                check(apps.remove_app(app_name));
+
+                // remove the application's files in the local copy of configs
+                for path in check(Store::default().remove(app_name)) {
+                    info!("Removed {:?}", path);
+                }
+            }
+            info!("Done");
+        }
+        SetManAction::New => {
+            info!("New application:");
+            let app_name = check(readline::read("Enter Application name"));
+            info!("Config path should be relative to home");
+            let app_config_path = check(readline::read("Config path"));
+            info!("Format: file_name.extension (space separated if > 1)");
+            let config_files = check(readline::read("File name(s) to save"));
+
+            let files_names = config_files.split_whitespace().map(String::from).collect();
+            check(apps.save_new_app(App::new(app_name, app_config_path, files_names)));
+        }
+        SetManAction::Add(app_name, file_paths) => {
+            job!("Adding files to {}", app_name);
+            add_files(&mut apps, app_name, file_paths);
+            info!("Done");
+        }
+        SetManAction::Forget(app_name, file_names) => {
+            job!("Forgetting files of {}", app_name);
+            let original = check(apps.find_app_by_name(app_name));
+            let mut app = original.clone();
+            for file in file_names.iter() {
+                // accept paths as well as plain file names
+                let file_name = match Path::new(file).file_name() {
+                    Some(name) => name.to_str().unwrap().to_string(),
+                    None => file.to_string(),
+                };
+                if !app.file_names.contains(&file_name) {
+                    warn!("{} is not tracked by {}", &file_name, app_name);
+                    continue;
+                }
+                app.file_names.retain(|f| f.ne(&file_name));
+                info!("Forgetting {}", &file_name);
+            }
+            if app.file_names.is_empty() {
+                warn!("{} no longer tracks any files", app_name);
+            }
+            apply_modification(&mut apps, &original, app);
+            info!("Done");
+        }
+        _ => fail(SetManError::InvalidOption),
+    }
+}
+
+// adds files, given as paths, to an app and saves them. the app is created if it
+// does not exist yet, with the files' directory as its config path
+fn add_files(apps: &mut Apps, app_name: &str, file_paths: &[String]) {
+    let cwd = check(std::env::current_dir());
+    let file_paths: Vec<PathBuf> = file_paths
+        .iter()
+        .map(|path| cwd.join(paths::expand_tilde(path)))
+        .collect();
+
+    let existing = apps.items.iter().find(|app| app.name == app_name).cloned();
+    let mut app = match existing.clone() {
+        Some(app) => app,
+        None => {
+            let config_path = match file_paths.first().and_then(|path| path.parent()) {
+                Some(dir) => dir.to_path_buf(),
+                None => fail(SetManError::InvalidOption),
+            };
+            info!("Creating {} with config path {:?}", app_name, &config_path);
+            App::new(
+                app_name.to_string(),
+                config_path.to_str().unwrap().to_string(),
+                vec![],
+            )
+        }
+    };
+
+    let mut added: Vec<String> = Vec::new();
+    for path in file_paths.iter() {
+        if !path.is_file() {
+            fail(SetManError::NotAFile(path.clone()));
+        }
+        if path.parent() != Some(app.config_path.as_path()) {
+            fail(SetManError::ConfigPathMismatch(
+                path.clone(),
+                app.config_path.clone(),
+                app.name.clone(),
+            ));
+        }
+        let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
+        if app.file_names.contains(&file_name) {
+            info!("{} is already tracked", &file_name);
+        } else {
+            app.file_names.push(file_name.clone());
+        }
+        added.push(file_name);
+    }
+
+    print_report(&check(Store::default().save_files(&app, &added)), "Saved");
+    match existing {
+        Some(_app) => check(apps.replace_app(app_name, app)),
+        None => check(apps.save_new_app(app)),
+    }
+}
+
+fn modify_application(apps: &mut Apps, app_name: &str, edit: bool) -> Result<(), IOError> {
+    let original = check(apps.find_app_by_name(app_name));
+    let app = match edit {
+        true => match edit_application(apps, &original)? {
+            Some(app) => app,
+            None => {
+                info!("Nothing to modify");
+                return Ok(());
+            }
+        },
+        false => modify_interactively(original.clone())?,
+    };
+    check(apps.validate_replacement(&original.name, &app));
+
+    // make sure user wants to modify the application
+    if readline::are_you_sure("modify ".to_owned() + app_name)? {
+        apply_modification(apps, &original, app);
+        info!("Done");
+    };
+    Ok(())
+}
+
+fn modify_interactively(mut app: App) -> Result<App, IOError> {
+    let mod_options = vec![
+        "Name",
+        "Config path",
+        "Replace a file name",
+        "Add files",
+        "Remove files",
+        "Reorder files",
+        "Tags",
+    ];
+    match readline::select(mod_options)? {
+        0 => app.name = readline::read("Enter a new name")?,
+        1 => {
+            let rel_path = readline::read("Enter a new config path")?;
+            let config_path = paths::get_absolute_path(&rel_path);
+            app.config_path = config_path;
+        }
+        2 => {
+            let mut file_names = app.file_names;
+            let file_names_str = file_names.iter().map(|s| &**s).collect();
+            let file_index: usize = readline::select(file_names_str)?;
+
+            let new_file_name = readline::read("Enter a new file name")?;
+            file_names.remove(file_index);
+            file_names.insert(file_index, new_file_name);
+            app.file_names = file_names;
+        }
+        3 => {
+            info!("Format: file_name.extension (space separated if > 1)");
+            let new_file_names = readline::read("File name(s) to add")?;
+            for file_name in new_file_names.split_whitespace() {
+                if !app.file_names.iter().any(|f| f == file_name) {
+                    app.file_names.push(file_name.to_string());
+                }
+            }
+        }
+        4 => {
+            let file_names_str = app.file_names.iter().map(|s| &**s).collect();
+            let indices = readline::multi_select(file_names_str)?;
+            app.file_names = app
+                .file_names
+                .iter()
+                .enumerate()
+                .filter(|(i, _f)| !indices.contains(i))
+                .map(|(_i, f)| f.clone())
+                .collect();
+        }
+        5 => {
+            let file_names_str = app.file_names.iter().map(|s| &**s).collect();
+            let file_index: usize = readline::select(file_names_str)?;
+            info!("Select the new position");
+            let positions: Vec<String> =
+                (1..=app.file_names.len()).map(|p| p.to_string()).collect();
+            let new_index = readline::select(positions.iter().map(|s| &**s).collect())?;
+            let file_name = app.file_names.remove(file_index);
+            app.file_names.insert(new_index, file_name);
+        }
+        6 => {
+            info!("Format: tag (space separated if > 1)");
+            let tags = readline::read("Tags")?;
+            app.tags = tags.split_whitespace().map(String::from).collect();
+        }
+        _ => fail(SetManError::InvalidOption),
+    }
+    Ok(app)
+}
+
+// opens the app's TOML in the user's editor until it is valid or the user gives up
+fn edit_application(apps: &Apps, app: &App) -> Result<Option<App>, IOError> {
+    let mut text = toml::to_string(app).unwrap();
+    loop {
+        let edited = match readline::edit(&text)? {
+            Some(edited) => edited,
+            None => return Ok(None),
+        };
+        let result = match toml::from_str::<App>(&edited) {
+            Ok(new_app) => apps
+                .validate_replacement(&app.name, &new_app)
+                .map(|_| new_app)
+                .map_err(|e| SetManError::InvalidApp(e.to_string())),
+            Err(e) => Err(SetManError::InvalidApp(e.to_string())),
+        };
+        match result {
+            Ok(new_app) => return Ok(Some(new_app)),
+            Err(e) => {
+                warn!("{}", e);
+                if !readline::are_you_sure("edit it again".to_string())? {
+                    return Ok(None);
+                }
+                text = edited;
+            }
+        }
+    }
+}
+
+// writes `app` in place of `original` and brings the store up to date with it
+fn apply_modification(apps: &mut Apps, original: &App, app: App) {
+    let store = Store::default();
+    if original.name != app.name && store.app_path(&original.name).exists() {
+        job!(
+            "Moving {:?} to {:?}",
+            store.app_path(&original.name),
+            store.app_path(&app.name)
+        );
+    }
+    for file_name in original.file_names.iter() {
+        if !app.file_names.contains(file_name) {
+            info!("Removing {} from the store", file_name);
+        }
+    }
+    print_report(&check(store.update_app(original, &app)), "Saved");
+    check(apps.replace_app(&original.name, app));
+}
+
+pub fn watch(push_interval: Option<Duration>, profile: Option<&str>) {
+    let apps = load_apps();
+    let (tx, rx) = channel();
+    let mut watcher = check(notify::watcher(tx, Duration::from_secs(2)));
+
+    // several apps can share a config directory, only watch each directory once
+    let mut watched: Vec<&Path> = Vec::new();
+    for app in apps.items.iter() {
+        if !app.config_path.exists() {
+            warn!(
+                "Config path {:?} for {} does not exist, skipping",
+                app.config_path, app.name
+            );
+            continue;
+        }
+        if !watched.contains(&app.config_path.as_path()) {
+            check(watcher.watch(&app.config_path, RecursiveMode::NonRecursive));
+            watched.push(&app.config_path);
+        }
+        info!("Watching {} in {:?}", app.name, app.config_path);
+    }
+
+    let store = Store::default();
+    let mut changed_apps: Vec<String> = Vec::new();
+    let mut last_change = Instant::now();
+    job!("Waiting for changes");
+    loop {
+        let event = match push_interval {
+            Some(interval) if !changed_apps.is_empty() => {
+                rx.recv_timeout(interval.saturating_sub(last_change.elapsed()))
+            }
+            _ => rx.recv().map_err(|_e| RecvTimeoutError::Disconnected),
+        };
+
+        let changed_path = match event {
+            Ok(DebouncedEvent::Create(path))
+            | Ok(DebouncedEvent::Write(path))
+            | Ok(DebouncedEvent::Rename(_, path)) => path,
+            Ok(DebouncedEvent::Error(e, path)) => {
+                warn!("Watch error for {:?}: {}", path, e);
+                continue;
+            }
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => {
+                let commit_msg = format!("Update {} (setman watch)", changed_apps.join(", "));
+                job!("Pushing changes for {}", changed_apps.join(", "));
+                let _lock = match SetManLock::wait() {
+                    Ok(lock) => lock,
+                    Err(e) => fail(e),
+                };
+                push_settings(Some(&commit_msg), profile);
+                changed_apps.clear();
+                job!("Waiting for changes");
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => fail("File watcher stopped unexpectedly"),
+        };
+
+        let (dir, file_name) = match (changed_path.parent(), changed_path.file_name()) {
+            (Some(dir), Some(file_name)) => (dir, file_name.to_str().unwrap().to_string()),
+            _ => continue,
+        };
+        for app in apps.items.iter() {
+            if app.config_path != dir || !app.file_names.contains(&file_name) {
+                continue;
+            }
+            let _lock = match SetManLock::wait() {
+                Ok(lock) => lock,
+                Err(e) => fail(e),
+            };
+            let report = check(store.save_files(app, std::slice::from_ref(&file_name)));
+            for warning in report.warnings.iter() {
+                warn!("{}", warning);
+            }
+            if !report.files.is_empty() {
+                info!("Saved {} for {}", &file_name, &app.name);
+            }
+
+            if !changed_apps.contains(&app.name) {
+                changed_apps.push(app.name.clone());
+            }
+            last_change = Instant::now();
+        }
+        // changed apps are only tracked when they are to be pushed
+        if push_interval.is_none() {
+            changed_apps.clear();
+        }
+    }
+}
+
+/// Prints how the app's installed files differ from the saved ones.
+pub fn diff_apps(selection: AppSelection) {
+    let apps = load_apps();
+    for app in select_apps(&apps, selection) {
+        diff_app(&app);
+    }
+}
+
+fn diff_app(app: &App) {
+    let store = Store::default();
+
+    job!("Comparing {} to its saved settings", &app.name);
+    let diffs = check(store.diff(app));
+    for diff in diffs.iter() {
+        match diff {
+            FileDiff::OnlySaved(file) => {
+                warn!("{} only exists in {:?}", file, store.app_path(&app.name))
+            }
+            FileDiff::OnlyInstalled(file) => {
+                warn!("{} only exists in {:?}", file, &app.config_path)
+            }
+            FileDiff::Large(file) => info!("Large files {} differ", file),
+            FileDiff::Binary(file) => info!("Binary files {} differ", file),
+            FileDiff::Text(_file, patch) => {
+                for line in patch.lines() {
+                    match line.chars().next() {
+                        Some('+') if !line.starts_with("+++") => println!("{}", line.green()),
+                        Some('-') if !line.starts_with("---") => println!("{}", line.red()),
+                        Some('@') => println!("{}", line.cyan()),
+                        _ => println!("{}", line),
+                    }
+                }
+            }
+        }
+    }
+    match diffs.len() {
+        0 => info!("No differences"),
+        n => info!("{} file(s) differ", n),
+    }
+}
+
+pub fn restore_app(app_name: &str, at: &str, install: bool, profile: Option<&str>) {
+    let apps = load_apps();
+    let app = check(apps.find_app_by_name(app_name));
+
+    let engine = sync_engine(profile);
+    let revision = match engine.find_revision(at) {
+        Ok(revision) => revision,
+        Err(e) => fail(format!("Could not find a revision for '{}': {}", at, e)),
+    };
+    info!("Found revision {}: {}", revision.id, revision.summary);
+
+    if !check(readline::are_you_sure(format!(
+        "restore {} from revision {}",
+        app_name, revision.id
+    ))) {
+        return;
+    }
+
+    job!("Restoring {}", app_name);
+    print_report(
+        &check(engine.restore(&apps, &app, &revision.id)),
+        "Restored",
+    );
+
+    if install {
+        install_app(&app);
+    }
+    info!("Done");
+}
+
+pub fn export_archive(file: &str) {
+    let apps = load_apps();
+    let path = paths::expand_tilde(file);
+    job!("Exporting to {:?}", &path);
+    let manifest = check(archive::export(&apps, &Store::default(), &path));
+    info!(
+        "Exported {} application(s) and {} file(s)",
+        apps.items.len(),
+        manifest.files.len()
+    );
+}
+
+/// Imports an archive created by `export_archive`. `install` holds the apps to install
+/// afterwards, if it is empty the user picks them.
+pub fn import_archive(file: &str, install: Option<Vec<String>>) {
+    let mut apps = load_apps();
+    let path = paths::expand_tilde(file);
+    job!("Verifying {:?}", &path);
+    let unpacked = check(archive::unpack(&path));
+    info!(
+        "Archive created by setman {} holds {} file(s)",
+        unpacked.manifest.setman_version,
+        unpacked.manifest.files.len()
+    );
+    for name in install.iter().flatten() {
+        if !unpacked.apps.items.iter().any(|app| &app.name == name) {
+            fail(format!("{} is not in the archive", name));
+        }
+    }
+    for app in unpacked.apps.items.iter() {
+        print_app!(app, false);
+        if apps.items.iter().any(|a| a.name == app.name) {
+            warn!("{} already exists and will be replaced", &app.name);
+        }
+    }
+    if !check(readline::are_you_sure(format!(
+        "import {} application(s)",
+        unpacked.apps.items.len()
+    ))) {
+        return;
+    }
+
+    job!("Importing {:?}", &path);
+    let store = Store::default();
+    let imported = check(unpacked.import(&mut apps, &store));
+    info!("Imported {} application(s)", imported.len());
+
+    let to_install: Vec<&App> = match install {
+        None => return,
+        Some(names) if names.is_empty() => {
+            let app_names = imported.iter().map(|app| &*app.name).collect();
+            let indices = check(readline::multi_select(app_names));
+            indices.iter().map(|i| &imported[*i]).collect()
+        }
+        Some(names) => imported
+            .iter()
+            .filter(|app| names.contains(&app.name))
+            .collect(),
+    };
+    for app in to_install {
+        install_app(app);
+    }
+    info!("Done");
+}
+
+pub fn import_dotfiles(source: &str) {
+    match dotfiles::is_git_url(source) {
+        true => job!("Cloning {}", source),
+        false => job!("Reading {}", source),
+    }
+    let dotfiles = check(dotfiles::open(source));
+
+    job!("Scanning {:?}", dotfiles.root());
+    let proposals = check(dotfiles.propose_apps());
+    if proposals.is_empty() {
+        warn!("Found nothing to import");
+        return;
+    }
+
+    let mut apps = load_apps();
+    let store = Store::default();
+    let mut imported: Vec<String> = Vec::new();
+    for mut proposal in proposals {
+        // make sure the name is not taken
+        proposal.app.name = dotfiles::free_name(&apps, &proposal.app.name);
+
+        print_app!(&proposal.app, true);
+        match check(readline::select(vec!["Import", "Skip", "Rename"])) {
+            0 => {}
+            1 => continue,
+            2 => {
+                proposal.app.name = check(readline::read("Enter a new name"));
+                if apps.items.iter().any(|a| a.name == proposal.app.name) {
+                    warn!("{} already exists, skipping", &proposal.app.name);
+                    continue;
+                }
+            }
+            _ => fail(SetManError::InvalidOption),
+        }
+
+        imported.push(check(proposal.import(&mut apps, &store)).name);
+    }
+    info!("Imported {} application(s)", imported.len());
+}
+
+pub fn compare_upstream(profile: Option<&str>) {
+    let engine = sync_engine(profile);
+    match check(engine.is_up_to_date()) {
+        true => info!("Local is up to date"),
+        false => warn!("Local is behind"),
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::args;
+
+use clap::Shell;
+use setman::fileman::Apps;
+
+// names of the positional arguments taking an application name
+const APP_ARGS: [&str; 2] = ["application", "app"];
+
+// full paths of the subcommands whose positional argument is an application name,
+// `remote add` and `remote remove` take remote names
+const APP_SUBCOMMANDS: [&str; 11] = [
+    "install app",
+    "uninstall app",
+    "save app",
+    "list",
+    "modify",
+    "remove",
+    "add",
+    "forget",
+    "diff",
+    "restore",
+    "sync",
+];
+
+// the subcommands of `APP_SUBCOMMANDS` which take a path after the application name,
+// fish must keep completing files for them
+const PATH_SUBCOMMANDS: [&str; 2] = ["add", "forget"];
+
+// succeeds when the subcommand path on the command line starts with one of its arguments
+const FISH_SUBCOMMAND_PATH: &str = r#"function __fish_setman_using_path
+    set -l tokens (commandline -opc)
+    set -e tokens[1]
+    set -l words
+    set -l skip_value 0
+    for token in $tokens
+        if test $skip_value = 1
+            set skip_value 0
+        else if test "$token" = --profile
+            set skip_value 1
+        else if not string match -q -- '-*' $token
+            set -a words $token
+        end
+    end
+    for path in $argv
+        set -l path_words (string split ' ' -- $path)
+        set -l n (count $path_words)
+        if test (count $words) -ge $n; and test "$words[1..$n]" = "$path"
+            return 0
+        end
+    end
+    return 1
+end
+"#;
+
+// asks setman for the app names when completing
+const LIST_APPS: &str = "setman __complete apps 2>/dev/null";
+
+/// Prints a completion script for `shell`. clap generates the static part, the
+/// application name arguments are then wired to `setman __complete apps`.
+pub fn generate(shell: &str) {
+    let mut script: Vec<u8> = Vec::new();
+    let clap_shell: Shell = shell.parse().unwrap();
+    args::build_app().gen_completions_to("setman", clap_shell, &mut script);
+    let script = String::from_utf8(script).unwrap();
+
+    let script = match shell {
+        "bash" => complete_apps_bash(&script),
+        "zsh" => complete_apps_zsh(&script),
+        _ => complete_apps_fish(&script),
+    };
+    print!("{}", script);
+}
+
+/// Prints the candidates of `kind` one per line, for use by the completion scripts.
+pub fn list(kind: &str) {
+    if kind != "apps" {
+        return;
+    }
+    // completing should never fail loudly
+    if let Ok(apps) = Apps::load() {
+        for app in apps.items {
+            println!("{}", app.name);
+        }
+    }
+}
+
+// the placeholders clap puts in the candidate lists are replaced with the app names
+fn complete_apps_bash(script: &str) -> String {
+    let mut script = script.to_string();
+    for arg in APP_ARGS.iter() {
+        for placeholder in [format!(" <{}>... ", arg), format!(" <{}> ", arg)].iter() {
+            script = script.replace(placeholder, &format!(" $({}) ", LIST_APPS));
+        }
+    }
+    script
+}
+
+fn complete_apps_zsh(script: &str) -> String {
+    let lines: Vec<String> = script
+        .lines()
+        .map(|line| {
+            let is_app_arg = APP_ARGS
+                .iter()
+                .any(|arg| line.contains(&format!(":{} -- ", arg)));
+            match is_app_arg && line.ends_with(":_files' \\") {
+                true => line.replace(":_files' \\", ":_setman_apps' \\"),
+                false => line.to_string(),
+            }
+        })
+        .collect();
+    let script = lines.join("\n");
+    let apps_function = format!(
+        "_setman_apps() {{\n    local -a apps\n    apps=(${{(f)\"$({})\"}})\n    _describe -t apps 'application' apps\n}}\n\n",
+        LIST_APPS
+    );
+    // the function has to exist before the script calls _setman at the end
+    match script.rfind("_setman \"$@\"") {
+        Some(pos) => format!("{}{}{}\n", &script[..pos], apps_function, &script[pos..]),
+        None => format!("{}\n{}", script, apps_function),
+    }
+}
+
+fn complete_apps_fish(script: &str) -> String {
+    let quote = |path: &&str| format!("'{}'", path);
+    let (with_paths, app_only): (Vec<&str>, Vec<&str>) = APP_SUBCOMMANDS
+        .iter()
+        .partition(|path| PATH_SUBCOMMANDS.contains(path));
+    let app_only: Vec<String> = app_only.iter().map(quote).collect();
+    let with_paths: Vec<String> = with_paths.iter().map(quote).collect();
+    format!(
+        "{}\n{}complete -c setman -n \"__fish_setman_using_path {}\" -f -a \"({})\"\n\
+         complete -c setman -n \"__fish_setman_using_path {}\" -a \"({})\"\n",
+        script,
+        FISH_SUBCOMMAND_PATH,
+        app_only.join(" "),
+        LIST_APPS,
+        with_paths.join(" "),
+        LIST_APPS
+    )
+}
@@ -0,0 +1,192 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::backend;
+use crate::error::Error;
+use crate::fileman::{self, TOMLError};
+use crate::paths;
+use crate::signing::AllowedSigners;
+
+use backend::{DirectoryBackend, GitBackend, SyncBackend, TarballBackend};
+use paths::Paths;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// The name of the upstream set with `setman init` among its mirrors
+pub const UPSTREAM_REMOTE: &str = "origin";
+
+/// Settings of setman itself, read from `config.toml` in the setman directory.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Config {
+    /// Profile to sync with when none is given. If unset, the upstream set
+    /// with `setman init` is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// If set, settings are only pulled if the revisions are signed with one of these keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_signers: Option<AllowedSigners>,
+    /// Mirrors of the upstream set with `setman init`, declared as `[[remotes]]`
+    /// tables and managed with `setman remote`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remotes: Vec<Remote>,
+}
+
+/// A named git repository, the name is used for the remote in setman's clone.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+}
+
+/// Where to sync to, declared as a `[profiles.<name>]` table.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum Profile {
+    /// A git repository, pushed to along with its mirrors. If it can't be reached
+    /// the first mirror which can is fetched from.
+    Git {
+        url: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        mirrors: Vec<Remote>,
+    },
+    /// A directory such as a mounted drive or a network share
+    Directory { path: PathBuf },
+    /// A gzipped tarball
+    Tarball { path: PathBuf },
+}
+
+impl Config {
+    /// Reads `config.toml`, returns an empty config if there is none.
+    pub fn load() -> Result<Config, Error> {
+        match fs::read_to_string(Paths::default().config_path) {
+            Ok(content) => Ok(toml::from_str(&content).map_err(TOMLError::from)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the profile named `name`, or the default profile if no name is given.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, Error> {
+        let name = match name.or(self.default_profile.as_deref()) {
+            Some(name) => name,
+            None => return self.upstream_profile(),
+        };
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile.clone()),
+            None => Err(Error::UnknownProfile(name.to_string())),
+        }
+    }
+
+    /// The profile of the upstream set with `setman init` and its mirrors.
+    pub fn upstream_profile(&self) -> Result<Profile, Error> {
+        let url = fs::read_to_string(Paths::default().upstream_path).unwrap_or_default();
+        match url.trim() {
+            "" => Err(Error::NoUpstream),
+            url => Ok(Profile::Git {
+                url: url.to_string(),
+                mirrors: self.remotes.clone(),
+            }),
+        }
+    }
+
+    /// Writes the config to `config.toml`.
+    pub fn save(&self) -> Result<(), Error> {
+        let toml = toml::to_string(&self).unwrap();
+        fileman::write_atomic(&Paths::default().config_path, toml)?;
+        Ok(())
+    }
+
+    /// The upstream set with `setman init`, named origin, followed by its mirrors.
+    pub fn upstream_remotes(&self) -> Result<Vec<Remote>, Error> {
+        let url = fs::read_to_string(Paths::default().upstream_path).unwrap_or_default();
+        if url.trim().is_empty() {
+            return Err(Error::NoUpstream);
+        }
+        let origin = Remote {
+            name: UPSTREAM_REMOTE.to_string(),
+            url: url.trim().to_string(),
+        };
+        Ok(std::iter::once(origin)
+            .chain(self.remotes.iter().cloned())
+            .collect())
+    }
+
+    /// Adds a mirror of the upstream and saves the config.
+    pub fn add_remote(&mut self, name: &str, url: &str) -> Result<(), Error> {
+        if !git2::Remote::is_valid_name(name) {
+            return Err(Error::InvalidRemoteName(name.to_string()));
+        }
+        if self
+            .upstream_remotes()?
+            .iter()
+            .any(|remote| remote.name == name)
+        {
+            return Err(Error::RemoteExists(name.to_string()));
+        }
+        self.remotes.push(Remote {
+            name: name.to_string(),
+            url: url.to_string(),
+        });
+        self.save()
+    }
+
+    /// Removes a remote and saves the config. Removing origin makes the first
+    /// mirror the upstream.
+    pub fn remove_remote(&mut self, name: &str) -> Result<Remote, Error> {
+        let remotes = self.upstream_remotes()?;
+        let removed = match remotes.iter().find(|remote| remote.name == name) {
+            Some(remote) => remote.clone(),
+            None => return Err(Error::UnknownRemote(name.to_string())),
+        };
+        if name == UPSTREAM_REMOTE {
+            if self.remotes.is_empty() {
+                return Err(Error::Unsupported(
+                    "origin is the only remote, change it with `setman init --upstream <url>`"
+                        .to_string(),
+                ));
+            }
+            let mirror = self.remotes.remove(0);
+            fileman::write_atomic(&Paths::default().upstream_path, &mirror.url)?;
+        } else {
+            self.remotes.retain(|remote| remote.name != name);
+        }
+        self.save()?;
+        Ok(removed)
+    }
+}
+
+impl Profile {
+    /// The backend syncing with the profile named `name`, None for the upstream set
+    /// with `setman init`. Git profiles keep their clone under that name, and refuse
+    /// to fetch commits which are not signed by one of `signers` if they are given.
+    pub fn backend(
+        &self,
+        name: Option<&str>,
+        signers: Option<&AllowedSigners>,
+    ) -> Box<dyn SyncBackend> {
+        match self {
+            Profile::Git { url, mirrors } => {
+                let clone_name = match name {
+                    Some(name) => format!("upstream.{}", name),
+                    None => "upstream".to_string(),
+                };
+                let backend = GitBackend::new(url, &clone_name)
+                    .with_mirrors(mirrors)
+                    .with_signers(signers.cloned());
+                Box::new(backend)
+            }
+            Profile::Directory { path } => Box::new(DirectoryBackend::new(expand(path))),
+            Profile::Tarball { path } => Box::new(TarballBackend::new(expand(path))),
+        }
+    }
+}
+
+fn expand(path: &Path) -> PathBuf {
+    paths::expand_tilde(path.to_str().unwrap_or_default())
+}
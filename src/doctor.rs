@@ -0,0 +1,233 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use colored::*;
+use git2::{Direction, ErrorClass, ErrorCode};
+use setman::config::{Config, Profile};
+use setman::fileman::{self, Apps, TOMLError};
+use setman::gitman::GitRepo;
+use setman::paths::Paths;
+use setman::Error;
+
+// the branch setman pushes to
+const BRANCH: &str = "refs/heads/main";
+
+struct Problem {
+    description: String,
+    fix: String,
+}
+
+impl Problem {
+    fn new(description: String, fix: &str) -> Problem {
+        Problem {
+            description,
+            fix: fix.to_string(),
+        }
+    }
+}
+
+/// Checks the local setup and the upstream and prints a fix for every problem found.
+pub fn run(profile: Option<&str>) {
+    let mut problems: Vec<Problem> = Vec::new();
+    problems.append(&mut check_apps());
+    problems.append(&mut check_upstream(profile));
+
+    if problems.is_empty() {
+        info!("No problems found");
+        return;
+    }
+    for problem in problems.iter() {
+        warn!("{}", problem.description);
+        println!(
+            "{}{} {}",
+            " ".repeat(4),
+            "Fix =>".bold().green(),
+            problem.fix
+        );
+    }
+    warn!("Found {} problem(s)", problems.len());
+}
+
+fn check_apps() -> Vec<Problem> {
+    let paths = Paths::default();
+    let mut problems: Vec<Problem> = Vec::new();
+
+    job!("Checking {:?}", &paths.applist_path);
+    let apps = match Apps::load() {
+        Ok(apps) => apps,
+        Err(TOMLError::FileError { source }) => {
+            problems.push(Problem::new(
+                format!("Could not read {:?}: {}", &paths.applist_path, source),
+                "Run `setman init` to create it",
+            ));
+            return problems;
+        }
+        Err(e) => {
+            problems.push(Problem::new(
+                format!("Could not load {:?}: {}", &paths.applist_path, e),
+                "Correct the error in the file by hand",
+            ));
+            return problems;
+        }
+    };
+    info!("{} application(s) defined", apps.items.len());
+
+    job!("Checking applications");
+    for app in apps.items.iter() {
+        let mut local_path = paths.settings_path.clone();
+        local_path.push(&app.name);
+
+        if !app.config_path.exists() {
+            problems.push(Problem::new(
+                format!(
+                    "Config path {:?} of {} does not exist",
+                    app.config_path, app.name
+                ),
+                &format!("Create it or change it with `setman modify {}`", app.name),
+            ));
+        }
+        for file in app.file_names.iter() {
+            if app.config_path.exists() && !app.config_path.join(file).exists() {
+                problems.push(Problem::new(
+                    format!(
+                        "{} of {} is missing in {:?}",
+                        file, app.name, app.config_path
+                    ),
+                    &format!("Run `setman install app {}` to install it", app.name),
+                ));
+            }
+            if !local_path.join(file).exists() {
+                problems.push(Problem::new(
+                    format!("{} of {} is missing in {:?}", file, app.name, local_path),
+                    &format!("Run `setman save app {}` to save it", app.name),
+                ));
+            }
+        }
+    }
+
+    job!("Checking {:?}", &paths.settings_path);
+    match fileman::get_dir_names_in_path(&paths.settings_path) {
+        Ok(dir_names) => {
+            for dir_name in dir_names {
+                if !apps.items.iter().any(|app| app.name == dir_name) {
+                    problems.push(Problem::new(
+                        format!(
+                            "{:?} has no application entry",
+                            paths.settings_path.join(&dir_name)
+                        ),
+                        "Define it with `setman new` or remove the directory",
+                    ));
+                }
+            }
+        }
+        Err(e) => problems.push(Problem::new(
+            format!("Could not read {:?}: {}", &paths.settings_path, e),
+            "Run `setman init` to create it",
+        )),
+    }
+    problems
+}
+
+fn check_upstream(profile_name: Option<&str>) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = Vec::new();
+
+    job!("Checking upstream");
+    let profile = match Config::load().and_then(|config| config.profile(profile_name)) {
+        Ok(profile) => profile,
+        Err(Error::NoUpstream) => {
+            problems.push(Problem::new(
+                "No upstream url is set".to_string(),
+                "Run `setman init --upstream <url>`",
+            ));
+            return problems;
+        }
+        Err(e) => {
+            problems.push(Problem::new(
+                format!("Could not load the sync profile: {}", e),
+                "Correct the profile in config.toml",
+            ));
+            return problems;
+        }
+    };
+    let (upstream_url, mirrors) = match profile {
+        Profile::Git { url, mirrors } => (url, mirrors),
+        profile => {
+            let backend = profile.backend(profile_name, None);
+            info!("Syncing with {}", backend.location());
+            if let Err(e) = backend.check() {
+                problems.push(Problem::new(
+                    format!("Could not reach {}: {}", backend.location(), e),
+                    "Create the directory or mount the drive it is on",
+                ));
+            }
+            return problems;
+        }
+    };
+    info!("Upstream is {}", upstream_url);
+    problems.append(&mut check_git_remote(&upstream_url));
+    for mirror in mirrors.iter() {
+        info!("Mirror {} is {}", mirror.name, mirror.url);
+        problems.append(&mut check_git_remote(&mirror.url));
+    }
+    problems
+}
+
+// checks that the git repository at `upstream_url` can be pushed to
+fn check_git_remote(upstream_url: &str) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = Vec::new();
+    let git_repo = match GitRepo::with_upstream(upstream_url.to_string()) {
+        Ok(git_repo) => git_repo,
+        Err(e) => {
+            problems.push(Problem::new(
+                format!("Could not read the git config: {}", e.message()),
+                "Correct the error in your git config",
+            ));
+            return problems;
+        }
+    };
+    let branches = match git_repo.remote_branches(Direction::Fetch) {
+        Ok(branches) => branches,
+        Err(e) if is_auth_error(&e) => {
+            problems.push(Problem::new(
+                format!("Could not authenticate with {}: {}", upstream_url, e.message()),
+                "Set up a git credential helper for the upstream, e.g. `git config --global credential.helper store`",
+            ));
+            return problems;
+        }
+        Err(e) => {
+            problems.push(Problem::new(
+                format!("Could not reach {}: {}", upstream_url, e.message()),
+                "Check your network connection or correct the url with `setman init --upstream <url>` or `setman remote`",
+            ));
+            return problems;
+        }
+    };
+
+    if let Err(e) = git_repo.remote_branches(Direction::Push) {
+        problems.push(Problem::new(
+            format!(
+                "Could not connect to {} for pushing: {}",
+                upstream_url,
+                e.message()
+            ),
+            "Make sure your credentials have write access to the upstream",
+        ));
+    }
+
+    if !branches.iter().any(|branch| branch == BRANCH) {
+        problems.push(Problem::new(
+            format!(
+                "Upstream has no {} branch",
+                BRANCH.trim_start_matches("refs/heads/")
+            ),
+            "Push an initial commit to the main branch of the upstream",
+        ));
+    }
+    problems
+}
+
+fn is_auth_error(e: &git2::Error) -> bool {
+    e.code() == ErrorCode::Auth
+        || e.class() == ErrorClass::Http && e.message().contains("authentication")
+}
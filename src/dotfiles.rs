@@ -0,0 +1,228 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::error::Error;
+use crate::fileman;
+use crate::gitman;
+use crate::paths;
+use crate::store;
+
+use fileman::{App, Apps};
+use paths::TempDir;
+use std::path::{Path, PathBuf};
+use store::Store;
+
+// files at the root of a dotfiles repo which are not dotfiles themselves
+const IGNORED: [&str; 5] = [
+    "README",
+    "LICENSE",
+    ".gitignore",
+    ".gitmodules",
+    ".stow-local-ignore",
+];
+
+/// A dotfiles tree to import apps from. Clones and the files of bare repositories
+/// are kept in a temporary directory until it is dropped.
+pub struct Dotfiles {
+    root: PathBuf,
+    _checkout: TempDir,
+}
+
+/// An app made from a group of files in a dotfiles tree.
+pub struct Proposal {
+    pub app: App,
+    source_dir: PathBuf,
+}
+
+/// Whether `source` is a git url rather than a path.
+pub fn is_git_url(source: &str) -> bool {
+    source.contains("://") || source.starts_with("git@")
+}
+
+/// Opens the dotfiles at `source`: a git url, the path of a bare dotfiles repository
+/// whose work tree is the home directory, or a plain directory.
+pub fn open(source: &str) -> Result<Dotfiles, Error> {
+    let checkout = TempDir::new("setman-import");
+    let root = match is_git_url(source) {
+        true => {
+            gitman::clone_url(source, checkout.path())?;
+            checkout.path().to_path_buf()
+        }
+        false => {
+            let path = paths::expand_tilde(source);
+            match gitman::open_bare(&path) {
+                Some(repo) => {
+                    gitman::export_head(&repo, checkout.path())?;
+                    checkout.path().to_path_buf()
+                }
+                None if path.is_dir() => path,
+                None => return Err(Error::Missing(path)),
+            }
+        }
+    };
+    Ok(Dotfiles {
+        root,
+        _checkout: checkout,
+    })
+}
+
+/// `name`, or `name` with the first free numeric suffix if an app has it already.
+pub fn free_name(apps: &Apps, name: &str) -> String {
+    let mut free_name = name.to_string();
+    let mut suffix = 2;
+    while apps.items.iter().any(|a| a.name == free_name) {
+        free_name = format!("{}-{}", name, suffix);
+        suffix += 1;
+    }
+    free_name
+}
+
+impl Dotfiles {
+    /// The directory the files are read from
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Groups the files into apps, one per target directory. Stow-style trees (only
+    /// non-hidden top-level directories) have one package per top-level directory,
+    /// anything else is treated as a mirror of the home directory.
+    pub fn propose_apps(&self) -> Result<Vec<Proposal>, Error> {
+        let root = &self.root;
+        let files: Vec<PathBuf> = fileman::find_files(root)?
+            .into_iter()
+            .filter(|file| {
+                let name = file.to_str().unwrap();
+                file.components().count() > 1 || !IGNORED.iter().any(|i| name.starts_with(i))
+            })
+            .collect();
+
+        let is_stow = files
+            .iter()
+            .all(|file| file.components().count() > 1 && !file.to_str().unwrap().starts_with('.'));
+
+        // (package, directory relative to home) -> file names
+        let mut groups: Vec<(String, PathBuf, Vec<String>)> = Vec::new();
+        for file in files {
+            let mut components = file.components();
+            let package = match is_stow {
+                true => components
+                    .next()
+                    .unwrap()
+                    .as_os_str()
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+                false => String::new(),
+            };
+            let home_rel = components.as_path();
+            let rel_dir = home_rel.parent().unwrap().to_path_buf();
+            let file_name = home_rel.file_name().unwrap().to_str().unwrap().to_string();
+            match groups
+                .iter_mut()
+                .find(|(p, dir, _files)| p == &package && dir == &rel_dir)
+            {
+                Some((_p, _dir, files)) => files.push(file_name),
+                None => groups.push((package, rel_dir, vec![file_name])),
+            }
+        }
+
+        let mut proposals: Vec<Proposal> = Vec::new();
+        for (package, rel_dir, file_names) in groups.iter() {
+            let dir_name = match rel_dir.file_name() {
+                Some(name) => name.to_str().unwrap().trim_start_matches('.').to_string(),
+                None => "home".to_string(),
+            };
+            let packages = groups.iter().filter(|(p, _d, _f)| p == package).count();
+            let name = match (is_stow, packages) {
+                (true, 1) => package.clone(),
+                (true, _) if &dir_name == package => package.clone(),
+                (true, _) => format!("{}-{}", package, dir_name),
+                (false, _) => dir_name,
+            };
+            let source_dir = root.join(package).join(rel_dir);
+            let app = App::new(
+                name,
+                rel_dir.to_str().unwrap().to_string(),
+                file_names.clone(),
+            );
+            proposals.push(Proposal { app, source_dir });
+        }
+        Ok(proposals)
+    }
+}
+
+impl Proposal {
+    /// Copies the files of the app into `store` and adds it to `apps`.
+    pub fn import(self, apps: &mut Apps, store: &Store) -> Result<App, Error> {
+        fileman::copy_files(
+            &self.app.file_names,
+            &self.source_dir,
+            &store.app_path(&self.app.name),
+        )?;
+        apps.save_new_app(self.app.clone())?;
+        Ok(self.app)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn dotfiles(files: &[&str]) -> Dotfiles {
+        let checkout = TempDir::new("setman-test-dotfiles");
+        for file in files {
+            let path = checkout.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, file).unwrap();
+        }
+        Dotfiles {
+            root: checkout.path().to_path_buf(),
+            _checkout: checkout,
+        }
+    }
+
+    fn names(proposals: &[Proposal]) -> Vec<&str> {
+        let mut names: Vec<&str> = proposals.iter().map(|p| p.app.name.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn home_mirrors_are_grouped_by_directory() {
+        let dotfiles = dotfiles(&[
+            "README.md",
+            ".bashrc",
+            ".config/kitty/kitty.conf",
+            ".config/kitty/theme.conf",
+        ]);
+        let proposals = dotfiles.propose_apps().unwrap();
+        assert_eq!(names(&proposals), vec!["home", "kitty"]);
+        let kitty = proposals.iter().find(|p| p.app.name == "kitty").unwrap();
+        assert_eq!(kitty.app.file_names.len(), 2);
+        assert_eq!(kitty.source_dir, dotfiles.root().join(".config/kitty"));
+    }
+
+    #[test]
+    fn stow_packages_are_named_after_the_package() {
+        let dotfiles = dotfiles(&[
+            "bash/.bashrc",
+            "nvim/.config/nvim/init.lua",
+            "nvim/.config/nvim-lua/init.lua",
+        ]);
+        let proposals = dotfiles.propose_apps().unwrap();
+        assert_eq!(names(&proposals), vec!["bash", "nvim", "nvim-nvim-lua"]);
+    }
+
+    #[test]
+    fn free_name_adds_the_first_free_suffix() {
+        let mut apps = Apps::empty();
+        for name in ["kitty", "kitty-2"].iter() {
+            apps.items
+                .push(App::new(name.to_string(), String::new(), vec![]));
+        }
+        assert_eq!(free_name(&apps, "bash"), "bash");
+        assert_eq!(free_name(&apps, "kitty"), "kitty-3");
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::fileman::{AppError, TOMLError};
+use crate::hooks::HookError;
+use crate::lock::LockError;
+use crate::thiserror;
+
+use git2::{ErrorClass, ErrorCode};
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+    #[error(transparent)]
+    Toml(#[from] TOMLError),
+    #[error(transparent)]
+    App(#[from] AppError),
+    #[error(transparent)]
+    Hook(#[from] HookError),
+    #[error(transparent)]
+    Lock(#[from] LockError),
+    #[error("No upstream url is set, run `setman init --upstream <url>`")]
+    NoUpstream,
+    #[error("{0:?} does not exist")]
+    Missing(PathBuf),
+    #[error("No profile named '{0}' in config.toml")]
+    UnknownProfile(String),
+    #[error("{0}")]
+    Unsupported(String),
+    #[error("Invalid archive: {0}")]
+    InvalidArchive(String),
+    #[error("{0:?} does not match the id it is stored under")]
+    CorruptObject(PathBuf),
+    #[error("Refusing to remove {0:?}")]
+    Protected(PathBuf),
+    #[error(
+        "Refusing to publish to {0:?}, it is neither empty nor a directory setman published to"
+    )]
+    ForeignDirectory(PathBuf),
+    #[error("Commit {0} conflicts with the upstream in {}, resolve it in {2:?} with git", .1.join(", "))]
+    Conflict(String, Vec<String>, PathBuf),
+    #[error("Failed to sign the commit: {0}")]
+    Signing(String),
+    #[error("Refusing to pull {0}: {1}")]
+    Unverified(String, String),
+    #[error("'{0}' is not a valid remote name")]
+    InvalidRemoteName(String),
+    #[error("A remote named '{0}' exists already")]
+    RemoteExists(String),
+    #[error("No remote named '{0}', see `setman remote list`")]
+    UnknownRemote(String),
+    #[error("{0}: {1}")]
+    Remote(String, git2::Error),
+    #[error("{0}, putting back the state from before failed as well: {1}")]
+    RestoreFailed(Box<Error>, Box<Error>),
+}
+
+impl Error {
+    /// Whether the error means the target could not be reached. The target refusing
+    /// the credentials or the operation is not a network error.
+    pub fn is_network(&self) -> bool {
+        match self {
+            Error::Git(e) | Error::Remote(_, e) => {
+                matches!(
+                    e.class(),
+                    ErrorClass::Net | ErrorClass::Os | ErrorClass::Http | ErrorClass::Ssh
+                ) && e.code() != ErrorCode::Auth
+            }
+            _ => false,
+        }
+    }
+}
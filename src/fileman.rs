@@ -2,6 +2,10 @@
 
 // SPDX-License-Identifier: BSD-2-Clause
 
+use crate::error::Error;
+use crate::hooks::Hooks;
+use crate::largefiles::LargeFilePolicy;
+use crate::lock::SetManLock;
 use crate::paths;
 use crate::regex;
 use crate::thiserror;
@@ -9,28 +13,73 @@ use crate::thiserror;
 use paths::Paths;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use std::io::Write;
+use std::{fs, path::Component, path::PathBuf};
 use std::{io::Result as IOResult, path::Path};
 use thiserror::Error;
+use toml::value::{Table, Value};
+use uuid::Uuid;
+
+/// Current version of the `apps.toml` schema
+pub const SCHEMA_VERSION: u32 = 1;
+
+// MIGRATIONS[n] upgrades a version n table to version n + 1
+const MIGRATIONS: [fn(&mut Table); 1] = [migrate_v0_to_v1];
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Apps {
+    #[serde(default)]
+    pub version: u32,
+    /// Directory of the side store for large files, defaults to `objects` in the setman directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub large_file_store: Option<PathBuf>,
     pub items: Vec<App>,
+    /// Set by `Apps::load` if the file was written with an older schema, the file
+    /// is migrated by the next write, see `Apps::save_migration`
+    #[serde(skip)]
+    pub migration: Option<Migration>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Migration {
+    pub from_version: u32,
+    pub backup_path: PathBuf,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct App {
     pub name: String,
     pub config_path: PathBuf,
     pub file_names: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<Hooks>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub preserve_mtimes: bool,
+    /// Size limit in bytes for each file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "LargeFilePolicy::is_default")]
+    pub large_files: LargeFilePolicy,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
 }
 
 #[derive(Error, Debug)]
-pub enum AppError<'a> {
-    #[error("An application with name '{0}' could not be found.")]
-    NotFound(&'a str),
-    #[error("An application with that name already exists")]
-    Duplicate,
+pub enum AppError {
+    #[error("An application with name '{0}' could not be found.{}", did_you_mean(.1))]
+    NotFound(String, Vec<String>),
+    #[error("An application with name '{0}' already exists")]
+    Duplicate(String),
+}
+
+fn did_you_mean(names: &[String]) -> String {
+    let names: Vec<String> = names.iter().map(|name| format!("'{}'", name)).collect();
+    match names.is_empty() {
+        true => String::new(),
+        false => format!(" Did you mean {}?", names.join(" or ")),
+    }
 }
 
 #[derive(Error, Debug)]
@@ -40,11 +89,18 @@ pub enum TOMLError {
         #[from]
         source: std::io::Error,
     },
-    #[error("Failed to parse toml")]
+    #[error("Failed to parse toml: {source}")]
     ParseError {
         #[from]
         source: toml::de::Error,
     },
+    #[error(
+        "Unsupported version {0}, the newest supported version is {}",
+        SCHEMA_VERSION
+    )]
+    UnsupportedVersion(i64),
+    #[error("Invalid application list: {0}")]
+    Invalid(String),
 }
 
 impl App {
@@ -54,55 +110,213 @@ impl App {
             name,
             config_path,
             file_names,
+            hooks: None,
+            preserve_mtimes: false,
+            max_file_size: None,
+            large_files: LargeFilePolicy::default(),
+            tags: vec![],
         }
     }
 }
 
 impl Apps {
-    pub fn new() -> Apps {
-        let file_content: String = match fs::read_to_string(Paths::default().applist_path) {
-            Ok(content) => Ok(content),
-            Err(e) => Err(TOMLError::FileError { source: e }),
+    /// An empty application list at the current schema version
+    pub fn empty() -> Apps {
+        Apps {
+            version: SCHEMA_VERSION,
+            large_file_store: None,
+            items: vec![],
+            migration: None,
+        }
+    }
+
+    /// Reads, migrates and validates `apps.toml`. Files written by an older version
+    /// of setman are only migrated in memory, nothing is written.
+    pub fn load() -> Result<Apps, TOMLError> {
+        let applist_path = Paths::default().applist_path;
+        let file_content = fs::read_to_string(&applist_path)?;
+        let (mut apps, version) = Apps::parse_versioned(&file_content)?;
+
+        if version < SCHEMA_VERSION {
+            let mut backup_path = applist_path;
+            backup_path.set_extension(format!("toml.v{}.bak", version));
+            apps.migration = Some(Migration {
+                from_version: version,
+                backup_path,
+            });
         }
-        .unwrap();
+        Ok(apps)
+    }
 
-        match toml::from_str::<Apps>(&file_content) {
-            Ok(toml) => toml,
-            Err(_e) => toml::from_str::<Apps>("items = []").unwrap(),
+    /// Writes a migrated `apps.toml` back in the current schema, the old file is kept
+    /// next to it as a backup. Takes the lock so only writing commands migrate the file.
+    pub fn save_migration(&self, _lock: &SetManLock) -> IOResult<()> {
+        match self.migration {
+            Some(_) => self.write_toml(),
+            None => Ok(()),
         }
     }
 
-    pub fn find_app_by_name(&'_ mut self, app_name: &str) -> Option<App> {
-        let pos: usize = match self.items.iter().position(|i| i.name == app_name) {
-            Some(pos) => pos,
-            None => {
-                panic!("{}", AppError::NotFound(app_name))
+    /// Parses, migrates and validates the content of an `apps.toml`.
+    pub fn parse(content: &str) -> Result<Apps, TOMLError> {
+        Ok(Apps::parse_versioned(content)?.0)
+    }
+
+    // returns the apps along with the version they were written with
+    fn parse_versioned(content: &str) -> Result<(Apps, u32), TOMLError> {
+        // parse into a plain table first so syntax errors are reported with line/column
+        let mut table = toml::from_str::<Table>(content)?;
+        let version = match table.get("version") {
+            Some(Value::Integer(version)) => *version,
+            Some(_v) => return Err(TOMLError::Invalid("version must be a number".to_string())),
+            None => 0,
+        };
+        if version < 0 || version > SCHEMA_VERSION as i64 {
+            return Err(TOMLError::UnsupportedVersion(version));
+        }
+
+        let apps = match version == SCHEMA_VERSION as i64 {
+            true => toml::from_str::<Apps>(content)?,
+            false => {
+                for (from, migrate) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+                    migrate(&mut table);
+                    table.insert("version".to_string(), Value::Integer(from as i64 + 1));
+                }
+                Value::Table(table).try_into::<Apps>()?
             }
         };
-        Some(self.items.get(pos)?.clone())
+        apps.validate()?;
+        Ok((apps, version as u32))
     }
 
-    pub fn find_apps_from_regex<'a>(&'a self, regex: &str) -> Option<Vec<&App>> {
-        let re = Regex::new(regex).unwrap();
+    fn validate(&self) -> Result<(), TOMLError> {
+        for (i, app) in self.items.iter().enumerate() {
+            if app.name.is_empty() || app.name.contains('/') {
+                return Err(TOMLError::Invalid(format!(
+                    "'{}' is not a valid application name",
+                    app.name
+                )));
+            }
+            if self.items[..i].iter().any(|a| a.name == app.name) {
+                return Err(TOMLError::Invalid(format!(
+                    "{} is defined more than once",
+                    app.name
+                )));
+            }
+            for (j, file_name) in app.file_names.iter().enumerate() {
+                if file_name.is_empty() || file_name.contains('/') {
+                    return Err(TOMLError::Invalid(format!(
+                        "'{}' of {} is not a valid file name",
+                        file_name, app.name
+                    )));
+                }
+                if app.file_names[..j].contains(file_name) {
+                    return Err(TOMLError::Invalid(format!(
+                        "{} is listed more than once in {}",
+                        file_name, app.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the list stays valid when `app` takes the place of the app named
+    /// `original_name`.
+    pub fn validate_replacement(&self, original_name: &str, app: &App) -> Result<(), TOMLError> {
+        let mut apps = self.clone();
+        apps.items.retain(|a| a.name != original_name);
+        apps.items.push(app.clone());
+        apps.validate()
+    }
+
+    pub fn find_app_by_name(&self, app_name: &str) -> Result<App, AppError> {
+        match self.items.iter().find(|i| i.name == app_name) {
+            Some(app) => Ok(app.clone()),
+            None => Err(AppError::NotFound(
+                app_name.to_string(),
+                self.similar_names(app_name),
+            )),
+        }
+    }
+
+    /// Names of the apps which are close to `app_name`, closest first.
+    pub fn similar_names(&self, app_name: &str) -> Vec<String> {
+        let wanted = app_name.to_lowercase();
+        let mut similar: Vec<(usize, &String)> = self
+            .items
+            .iter()
+            .map(|app| {
+                let name = app.name.to_lowercase();
+                (strsim::levenshtein(&wanted, &name), &app.name)
+            })
+            .filter(|(distance, name)| {
+                // allow about one typo per three characters
+                *distance <= std::cmp::max(1, wanted.len() / 3)
+                    || name.to_lowercase().contains(&wanted)
+            })
+            .collect();
+        similar.sort();
+        similar
+            .into_iter()
+            .take(3)
+            .map(|(_distance, name)| name.clone())
+            .collect()
+    }
+
+    pub fn find_apps_from_regex(&self, regex: &str) -> Result<Vec<&App>, regex::Error> {
+        let re = Regex::new(regex)?;
         let apps = self
             .items
             .iter()
             .filter(|app| re.is_match(&app.name))
             .collect();
-        Some(apps)
+        Ok(apps)
     }
 
-    pub fn save_new_app(&mut self, app: App) -> IOResult<()> {
-        for app_item in self.items.clone() {
-            if app_item.name.eq(&app.name) {
-                panic!("{}", AppError::Duplicate);
-            }
+    /// Apps with at least one of `tags`.
+    pub fn find_apps_by_tags(&self, tags: &[String]) -> Vec<&App> {
+        self.items
+            .iter()
+            .filter(|app| app.tags.iter().any(|tag| tags.contains(tag)))
+            .collect()
+    }
+
+    pub fn save_new_app(&mut self, app: App) -> Result<(), Error> {
+        if self.items.iter().any(|a| a.name == app.name) {
+            return Err(AppError::Duplicate(app.name).into());
         }
         self.items.push(app);
         self.write_toml()?;
         Ok(())
     }
 
+    /// Replaces the app named `app_name`, keeping its position in the list.
+    pub fn replace_app(&mut self, app_name: &str, app: App) -> Result<(), Error> {
+        let pos = match self.items.iter().position(|i| i.name == app_name) {
+            Some(pos) => pos,
+            None => {
+                let similar = self.similar_names(app_name);
+                return Err(AppError::NotFound(app_name.to_string(), similar).into());
+            }
+        };
+        self.items[pos] = app;
+        self.write_toml()?;
+        Ok(())
+    }
+
+    /// Adds `apps`, replacing apps with the same name in place.
+    pub fn merge_apps(&mut self, apps: Vec<App>) -> Result<(), Error> {
+        for app in apps {
+            match self.items.iter().position(|i| i.name == app.name) {
+                Some(pos) => self.items[pos] = app,
+                None => self.items.push(app),
+            }
+        }
+        self.write_toml()?;
+        Ok(())
+    }
+
     pub fn remove_app(&mut self, app_name: &str) -> IOResult<()> {
         self.items.retain(|a| a.name.ne(app_name));
         self.write_toml()?;
@@ -110,12 +324,35 @@ impl Apps {
     }
 
     fn write_toml(&self) -> IOResult<()> {
+        let applist_path = Paths::default().applist_path;
+        // never overwrite a file the user has to fix by hand
+        if let Ok(content) = fs::read_to_string(&applist_path) {
+            if let Err(e) = Apps::parse(&content) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Refusing to overwrite {:?}, it failed to parse: {}",
+                        applist_path, e
+                    ),
+                ));
+            }
+            // keep the file as it was before its first write in the current schema
+            if let Some(migration) = &self.migration {
+                if !migration.backup_path.exists() {
+                    write_atomic(&migration.backup_path, &content)?;
+                }
+            }
+        }
         let toml = toml::to_string(&self).unwrap();
-        fs::write(Paths::default().applist_path, &toml)?;
+        write_atomic(&applist_path, &toml)?;
         Ok(())
     }
 }
 
+fn migrate_v0_to_v1(_table: &mut Table) {
+    // version 0 files only lack the version field
+}
+
 pub fn get_dir_names_in_path(dir_path: &Path) -> IOResult<Vec<String>> {
     let read = Path::new(dir_path).read_dir()?;
     let mut result: Vec<String> = Vec::new();
@@ -128,40 +365,374 @@ pub fn get_dir_names_in_path(dir_path: &Path) -> IOResult<Vec<String>> {
     Ok(result)
 }
 
-pub fn copy_files(file_names: Vec<String>, source: &Path, dest: &Path) -> IOResult<()> {
-    assert!(source.exists());
+/// Returns the files in `file_names` whose content differs between `source` and `dest`.
+pub fn changed_files(file_names: &[String], source: &Path, dest: &Path) -> Vec<String> {
+    file_names
+        .iter()
+        .filter(|file| {
+            let source_content = fs::read(source.join(file)).ok();
+            let dest_content = fs::read(dest.join(file)).ok();
+            source_content.ne(&dest_content)
+        })
+        .cloned()
+        .collect()
+}
 
-    if !dest.exists() {
-        info!("Destination folder does not exist, creating it");
-        fs::create_dir(dest).unwrap();
+/// Recursively lists all files below `dir`, relative to `dir`. `.git` directories are skipped.
+pub fn find_files(dir: &Path) -> IOResult<Vec<PathBuf>> {
+    let mut result: Vec<PathBuf> = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(rel_dir) = dirs.pop() {
+        for entry in dir.join(&rel_dir).read_dir()? {
+            let entry = entry?;
+            if entry.file_name() == ".git" {
+                continue;
+            }
+            let rel_path = rel_dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(rel_path);
+            } else {
+                result.push(rel_path);
+            }
+        }
     }
+    result.sort();
+    Ok(result)
+}
 
-    job!("Copying files from {:?}", source);
-    for file in file_names {
-        println!("getting source path");
-        let mut source_path = source.to_path_buf();
-        source_path.push(&file);
+/// Writes `contents` to a temporary file next to `path` and renames it into place,
+/// so readers never see a partially written file.
+pub fn write_atomic<C: AsRef<[u8]>>(path: &Path, contents: C) -> IOResult<()> {
+    let tmp_path = tmp_path_for(path);
+    let result = fs::File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
 
-        // handle folder entries
+/// Like `fs::copy` but replaces `dest` atomically.
+/// Symlinks are written through instead of being replaced.
+pub fn copy_atomic(source: &Path, dest: &Path) -> IOResult<()> {
+    if let Ok(metadata) = fs::symlink_metadata(dest) {
+        if metadata.file_type().is_symlink() {
+            fs::copy(source, dest)?;
+            return Ok(());
+        }
+    }
+    let tmp_path = tmp_path_for(dest);
+    let result = fs::copy(source, &tmp_path).and_then(|_| fs::rename(&tmp_path, dest));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
 
-        let mut dest_path = dest.to_path_buf();
-        dest_path.push(&file);
+pub(crate) fn tmp_path_for(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap().to_str().unwrap();
+    path.with_file_name(format!(".{}.setman-tmp-{}", file_name, Uuid::new_v4()))
+}
+
+/// Copies `file_names` from `source` to `dest`, creating `dest` if needed.
+pub fn copy_files(file_names: &[String], source: &Path, dest: &Path) -> Result<(), Error> {
+    if !source.exists() {
+        return Err(Error::Missing(source.to_path_buf()));
+    }
+    if !dest.exists() {
+        fs::create_dir_all(dest)?;
+    }
 
+    for file in file_names {
+        let source_path = source.join(file);
         // check if source file exists before attempting copy
-        assert!(source_path.exists());
-        fs::copy(source_path, dest_path)?;
-        info!("Copied {} to {:?}", &file.bold(), &dest);
+        if !source_path.exists() {
+            return Err(Error::Missing(source_path));
+        }
+        copy_atomic(&source_path, &dest.join(file))?;
     }
     Ok(())
 }
 
-pub fn remove_files(conf_path: &Path) -> IOResult<()> {
-    job!("Removing files in {:?}", &conf_path);
-    let files = fs::read_dir(conf_path)?;
+/// The paths of the tracked files which exist in `conf_path`. File names must
+/// stay inside of it, and `conf_path` may not be the root, the home directory
+/// or above it.
+pub fn installed_files(file_names: &[String], conf_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    if paths::is_protected(conf_path) {
+        return Err(Error::Protected(conf_path.to_path_buf()));
+    }
+    let mut files: Vec<PathBuf> = Vec::new();
+    for file in file_names {
+        let path = conf_path.join(file);
+        let components: Vec<Component> = Path::new(file).components().collect();
+        if components.is_empty()
+            || components
+                .iter()
+                .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(Error::Protected(path));
+        }
+        // a symlink is removed, not what it points to
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if !metadata.is_dir() => files.push(path),
+            _ => {}
+        }
+    }
+    Ok(files)
+}
+
+/// Removes `files`, then the directories in `created_dirs` which that leaves empty
+/// and which are not protected. `created_dirs` are the directories an install
+/// created, deepest first. Returns the removed paths.
+pub fn remove_installed_files(
+    files: &[PathBuf],
+    created_dirs: &[PathBuf],
+) -> IOResult<Vec<PathBuf>> {
+    let mut removed: Vec<PathBuf> = Vec::new();
     for file in files {
-        let file_path = file?.path();
-        info!("Removing file {:?}", &file_path);
-        fs::remove_file(&file_path)?;
+        fs::remove_file(file)?;
+        removed.push(file.clone());
+    }
+    for dir in created_dirs {
+        let is_empty = match fs::read_dir(dir) {
+            Ok(mut entries) => entries.next().is_none(),
+            Err(_e) => false,
+        };
+        if is_empty && !paths::is_protected(dir) {
+            fs::remove_dir(dir)?;
+            removed.push(dir.clone());
+        }
+    }
+    Ok(removed)
+}
+
+/// `path` and those of its parents which do not exist, deepest first. These are
+/// the directories creating `path` creates.
+pub fn missing_dirs(path: &Path) -> Vec<PathBuf> {
+    path.ancestors()
+        .take_while(|dir| fs::symlink_metadata(dir).is_err())
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::with_home;
+
+    const V0: &str =
+        "[[items]]\nname = \"kitty\"\nconfig_path = \"/x/kitty\"\nfile_names = [\"kitty.conf\"]\n";
+
+    #[test]
+    fn every_older_version_has_a_migration() {
+        assert_eq!(MIGRATIONS.len(), SCHEMA_VERSION as usize);
+    }
+
+    #[test]
+    fn parse_versioned_migrates_old_files() {
+        let (apps, version) = Apps::parse_versioned(V0).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(apps.version, SCHEMA_VERSION);
+        assert_eq!(apps.items[0].name, "kitty");
+
+        let current = format!("version = {}\n{}", SCHEMA_VERSION, V0);
+        let (apps, version) = Apps::parse_versioned(&current).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        assert_eq!(apps.items.len(), 1);
+    }
+
+    #[test]
+    fn parse_versioned_rejects_bad_versions() {
+        let newer = format!("version = {}\nitems = []\n", SCHEMA_VERSION + 1);
+        assert!(matches!(
+            Apps::parse_versioned(&newer),
+            Err(TOMLError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            Apps::parse_versioned("version = -1\nitems = []\n"),
+            Err(TOMLError::UnsupportedVersion(-1))
+        ));
+        assert!(matches!(
+            Apps::parse_versioned("version = \"1\"\nitems = []\n"),
+            Err(TOMLError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_keys_are_errors_with_a_location() {
+        let typo = V0.replace("file_names", "file_name");
+        let e = Apps::parse(&format!("version = 1\n{}", typo)).unwrap_err();
+        assert!(e.to_string().contains("file_name"), "{}", e);
+        assert!(e.to_string().contains("line"), "{}", e);
+        assert!(Apps::parse("version = 1\nitem = []\n").is_err());
+        assert!(Apps::parse(&V0.replace("config_path", "confg_path")).is_err());
+    }
+
+    #[test]
+    fn replacements_are_validated_against_the_other_apps() {
+        let mut apps = Apps::parse(V0).unwrap();
+        apps.items
+            .push(App::new("bash".to_string(), String::new(), vec![]));
+        let kitty = apps.find_app_by_name("kitty").unwrap();
+
+        let mut renamed = kitty.clone();
+        renamed.name = "term".to_string();
+        assert!(apps.validate_replacement("kitty", &renamed).is_ok());
+        renamed.name = "bash".to_string();
+        assert!(apps.validate_replacement("kitty", &renamed).is_err());
+
+        let mut listed_twice = kitty.clone();
+        listed_twice.file_names.push("kitty.conf".to_string());
+        assert!(apps.validate_replacement("kitty", &listed_twice).is_err());
+        let mut nested = kitty;
+        nested.file_names = vec!["themes/dark.conf".to_string()];
+        assert!(apps.validate_replacement("kitty", &nested).is_err());
+    }
+
+    #[test]
+    fn load_does_not_write_and_migration_keeps_a_backup() {
+        with_home(|_home| {
+            let applist_path = Paths::default().applist_path;
+            fs::write(&applist_path, V0).unwrap();
+
+            let apps = Apps::load().unwrap();
+            let backup_path = apps.migration.as_ref().unwrap().backup_path.clone();
+            assert_eq!(fs::read_to_string(&applist_path).unwrap(), V0);
+            assert!(!backup_path.exists());
+
+            let lock = SetManLock::acquire().unwrap();
+            apps.save_migration(&lock).unwrap();
+            assert_eq!(fs::read_to_string(&backup_path).unwrap(), V0);
+            assert!(Apps::load().unwrap().migration.is_none());
+        })
+    }
+
+    fn names(file_names: &[&str]) -> Vec<String> {
+        file_names.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn installed_files_refuses_protected_paths() {
+        with_home(|home| {
+            let kitty = home.join(".config/kitty");
+            fs::create_dir_all(&kitty).unwrap();
+            let file_names = names(&["kitty.conf"]);
+
+            assert!(installed_files(&file_names, Path::new("/")).is_err());
+            assert!(installed_files(&file_names, home).is_err());
+            assert!(installed_files(&file_names, home.parent().unwrap()).is_err());
+            assert!(installed_files(&file_names, &home.join(".config/..")).is_err());
+            assert!(installed_files(&file_names, &kitty.join("../../..")).is_err());
+            // a symlink to the home directory is the home directory
+            std::os::unix::fs::symlink(home, home.join("link")).unwrap();
+            assert!(installed_files(&file_names, &home.join("link")).is_err());
+            assert!(installed_files(&file_names, &kitty).is_ok());
+        })
+    }
+
+    #[test]
+    fn installed_files_stay_inside_the_config_path() {
+        with_home(|home| {
+            let kitty = home.join(".config/kitty");
+            fs::create_dir_all(kitty.join("themes")).unwrap();
+            fs::write(home.join(".bashrc"), "").unwrap();
+            for file_name in ["../../.bashrc", "..", ".", "", "/etc/passwd"].iter() {
+                assert!(
+                    installed_files(&names(&[file_name]), &kitty).is_err(),
+                    "{}",
+                    file_name
+                );
+            }
+
+            fs::write(kitty.join("kitty.conf"), "").unwrap();
+            let file_names = names(&["kitty.conf", "themes", "missing.conf"]);
+            assert_eq!(
+                installed_files(&file_names, &kitty).unwrap(),
+                vec![kitty.join("kitty.conf")]
+            );
+        })
+    }
+
+    #[test]
+    fn removing_a_symlink_keeps_its_target() {
+        with_home(|home| {
+            let kitty = home.join(".config/kitty");
+            fs::create_dir_all(&kitty).unwrap();
+            fs::write(home.join("target.conf"), "").unwrap();
+            std::os::unix::fs::symlink(home.join("target.conf"), kitty.join("kitty.conf")).unwrap();
+
+            let files = installed_files(&names(&["kitty.conf"]), &kitty).unwrap();
+            remove_installed_files(&files, &[]).unwrap();
+            assert!(fs::symlink_metadata(kitty.join("kitty.conf")).is_err());
+            assert!(home.join("target.conf").exists());
+        })
+    }
+
+    #[test]
+    fn only_created_directories_are_removed() {
+        with_home(|home| {
+            // the user made .config, install makes the rest
+            fs::create_dir_all(home.join(".config")).unwrap();
+            let conf_path = home.join(".config/kitty/themes");
+            let created = missing_dirs(&conf_path);
+            assert_eq!(created, vec![conf_path.clone(), home.join(".config/kitty")]);
+
+            fs::create_dir_all(&conf_path).unwrap();
+            fs::write(conf_path.join("dark.conf"), "").unwrap();
+            let files = installed_files(&names(&["dark.conf"]), &conf_path).unwrap();
+            let removed = remove_installed_files(&files, &created).unwrap();
+            assert_eq!(removed.len(), 3);
+            assert!(home.join(".config").exists());
+            assert!(!home.join(".config/kitty").exists());
+        })
+    }
+
+    #[test]
+    fn directories_which_existed_before_or_hold_other_files_are_kept() {
+        with_home(|home| {
+            let kitty = home.join(".config/kitty");
+            fs::create_dir_all(&kitty).unwrap();
+            fs::write(kitty.join("kitty.conf"), "").unwrap();
+            let files = installed_files(&names(&["kitty.conf"]), &kitty).unwrap();
+            remove_installed_files(&files, &[]).unwrap();
+            assert!(kitty.exists());
+
+            fs::write(kitty.join("kitty.conf"), "").unwrap();
+            fs::write(kitty.join("mine.conf"), "").unwrap();
+            let files = installed_files(&names(&["kitty.conf"]), &kitty).unwrap();
+            remove_installed_files(&files, std::slice::from_ref(&kitty)).unwrap();
+            assert!(kitty.join("mine.conf").exists());
+
+            // protected directories are never removed, even if recorded
+            let empty_home = vec![home.to_path_buf()];
+            fs::remove_dir_all(home.join(".config")).unwrap();
+            for entry in fs::read_dir(home).unwrap() {
+                fs::remove_file(entry.unwrap().path()).unwrap();
+            }
+            assert!(remove_installed_files(&[], &empty_home).unwrap().is_empty());
+            assert!(home.exists());
+        })
+    }
+
+    #[test]
+    fn write_refuses_to_overwrite_a_broken_file() {
+        with_home(|_home| {
+            let applist_path = Paths::default().applist_path;
+            // a syntax error, and a file which is valid toml but fails to load
+            let unknown_key = format!("version = {}\ntheme = \"dark\"\n", SCHEMA_VERSION);
+            for broken in ["version = 1\n[[items]\n", &unknown_key] {
+                fs::write(&applist_path, broken).unwrap();
+
+                let mut apps = Apps::empty();
+                let app = App::new("kitty".to_string(), ".config/kitty".to_string(), vec![]);
+                let e = apps.save_new_app(app).unwrap_err();
+                assert!(e.to_string().contains("Refusing to overwrite"), "{}", e);
+                assert_eq!(fs::read_to_string(&applist_path).unwrap(), broken);
+            }
+        })
     }
-    Ok(())
 }
@@ -2,119 +2,337 @@
 
 // SPDX-License-Identifier: BSD-2-Clause
 
-use crate::paths;
-use crate::readline;
-use crate::thiserror;
+use crate::config::UPSTREAM_REMOTE;
+use crate::error;
+use crate::fileman;
+use crate::paths::TempDir;
+use crate::signing::{self, AllowedSigners};
 
 use git2::{
-    build::RepoBuilder, Commit, Config, Cred, Error, FetchOptions, IndexAddOption, Oid,
-    PushOptions, RemoteCallbacks, Repository, Signature, Tree,
+    build::{CheckoutBuilder, RepoBuilder},
+    Commit, Config, Cred, Direction, Error, ErrorClass, ErrorCode, FetchOptions, IndexAddOption,
+    ObjectType, Oid, PushOptions, Remote, RemoteCallbacks, Repository, Signature, Sort, Tree,
+    TreeWalkMode, TreeWalkResult,
 };
-use paths::Paths;
+use std::cell::RefCell;
 use std::path::PathBuf;
 use std::{fs, path::Path};
-use std::{
-    fs::File,
-    io::{LineWriter, Write},
-};
-use thiserror::Error;
-use uuid::Uuid;
+
+/// The branch settings are committed to
+pub const MAIN_BRANCH: &str = "refs/heads/main";
 
 pub struct GitRepo {
     pub repo_path: PathBuf,
     upstream_url: String,
+    // the name of the upstream's remote in the clone
+    remote_name: String,
     git_config: Config,
-}
-
-#[derive(Error, Debug)]
-enum GitError<'a> {
-    #[error("Failed to open {0} as repository: {1}")]
-    RepoOpen(&'a PathBuf, git2::Error),
-    #[error("Failed to create commit: {0}")]
-    CreateCommit(git2::Error),
-    #[error("Failed to get parent commit: {0}")]
-    RevParseError(git2::Error),
-    #[error("Failed to get repo index")]
-    GetIndexErr,
+    // fetched upstream commits must be signed by one of these if set
+    signers: Option<AllowedSigners>,
+    // removes the clone of `with_upstream` when the repo is dropped
+    _tmp_dir: Option<TempDir>,
 }
 
 impl GitRepo {
-    pub fn new() -> Self {
-        let git_config = Config::open_default().unwrap();
-        let tmp_dir_name = format!("setman-tmp-{}", &Uuid::new_v4().to_string());
-        let repo_path: PathBuf = [r"/tmp", &tmp_dir_name].iter().collect();
+    /// Clones `upstream_url` into a new temporary directory, which is removed along
+    /// with the returned repo.
+    pub fn with_upstream(upstream_url: String) -> Result<Self, Error> {
+        let tmp_dir = TempDir::new("setman-tmp");
+        let mut git_repo = Self::at(upstream_url, tmp_dir.path().to_path_buf())?;
+        git_repo._tmp_dir = Some(tmp_dir);
+        Ok(git_repo)
+    }
 
-        let upstream_url = match fs::read_to_string(Paths::default().upstream_path) {
-            Ok(url) => url.replace('\n', ""),
-            Err(_e) => {
-                let url = readline::read("Enter your repo's upstream url").unwrap();
-                fs::write(Paths::default().upstream_path, &url).unwrap();
-                url
-            }
-        };
-        Self {
+    /// Clones `upstream_url` into `repo_path`.
+    pub fn at(upstream_url: String, repo_path: PathBuf) -> Result<Self, Error> {
+        Self::with_remote(UPSTREAM_REMOTE.to_string(), upstream_url, repo_path)
+    }
+
+    /// Like `at`, with the upstream known as `remote_name` in the clone. Several
+    /// upstreams can share one clone this way.
+    pub fn with_remote(
+        remote_name: String,
+        upstream_url: String,
+        repo_path: PathBuf,
+    ) -> Result<Self, Error> {
+        let git_config = Config::open_default()?;
+        Ok(Self {
             repo_path,
             upstream_url,
+            remote_name,
             git_config,
-        }
+            signers: None,
+            _tmp_dir: None,
+        })
     }
 
-    pub fn get_dir_names(&self) -> Vec<String> {
-        job!("Getting directories from git repo");
-        let directories = fs::read_dir(&self.repo_path).unwrap();
+    /// Refuses upstream commits which are not signed by one of `signers` when
+    /// fetching, before they are checked out or rebased onto.
+    pub fn verifying(mut self, signers: Option<AllowedSigners>) -> Self {
+        self.signers = signers;
+        self
+    }
 
-        let mut dirs_names: Vec<String> = Vec::new();
+    pub fn upstream_url(&self) -> &str {
+        &self.upstream_url
+    }
+
+    /// The upstream's main branch as of the last fetch
+    pub fn upstream_branch(&self) -> String {
+        format!("refs/remotes/{}/main", self.remote_name)
+    }
 
-        for dir in directories {
-            let tmp = dir.unwrap();
-            // filter the entries to remove files and .git dir
-            if tmp.path().is_dir() && tmp.file_name().ne(".git") {
-                let dir_path = tmp.file_name().to_str().unwrap().to_string();
-                info!("Found directory: {}", dir_path);
-                dirs_names.push(dir_path);
+    /// Connects to the upstream to make sure it is reachable with the current credentials.
+    pub fn check_remote(&self) -> Result<(), Error> {
+        self.remote_branches(Direction::Fetch)?;
+        Ok(())
+    }
+
+    /// Connects to the upstream in `direction` and lists its branches.
+    pub fn remote_branches(&self, direction: Direction) -> Result<Vec<String>, Error> {
+        let mut remote = Remote::create_detached(&self.upstream_url)?;
+        let connection = remote.connect_auth(direction, Some(self.gen_callbacks()), None)?;
+        // listing the heads of an empty remote is unsound in git2, an empty remote
+        // advertises no HEAD so check for that first
+        if let Err(e) = connection.default_branch() {
+            if e.code() == ErrorCode::NotFound {
+                return Ok(vec![]);
             }
         }
-        dirs_names
+        let branches = connection
+            .list()?
+            .iter()
+            .map(|head| head.name().to_string())
+            .filter(|name| name.starts_with("refs/heads/"))
+            .collect();
+        Ok(branches)
     }
 
-    pub fn push_changes(&self) -> Result<(), Error> {
+    /// Commits everything in the clone and pushes it, returns the new commit's id.
+    pub fn push_changes(&self, commit_msg: &str) -> Result<Oid, error::Error> {
+        let repo = Repository::open(&self.repo_path)?;
+        let new_commit_id = self.commit_changes(&repo, commit_msg)?;
+        self.push_commits(&repo)?;
+        Ok(new_commit_id)
+    }
+
+    /// Opens the clone in `repo_path`, cloning the upstream first if there is none.
+    pub fn open_or_clone(&self) -> Result<Repository, error::Error> {
         match Repository::open(&self.repo_path) {
-            Ok(repo) => {
-                let signature = repo.signature()?;
-                let mut index = match repo.index() {
-                    Ok(ind) => ind,
-                    Err(_e) => panic!("{}", GitError::GetIndexErr),
-                };
-
-                // git add .
-                job!("Staging files for commit");
-                index.add_all(["."].iter(), IndexAddOption::DEFAULT, None)?;
-                index.write()?;
-
-                // get index tree
-                let tree_id = index.write_tree()?;
-                let tree = repo.find_tree(tree_id)?;
-
-                let parent = self.get_parent_commit(&repo).unwrap();
-                let new_commit_id = self
-                    .create_commit(&repo, &signature, &tree, &parent)
-                    .unwrap();
-
-                self.save_commit_id(new_commit_id).unwrap();
-
-                let callbacks = self.gen_callbacks();
-                let mut push_opts = PushOptions::new();
-                push_opts.remote_callbacks(callbacks);
-
-                // push to remote origin
-                let mut origin = repo.find_remote("origin")?;
-                job!("Pushing to remote: {}", origin.name().unwrap());
-                origin.push(&["refs/heads/main"], Some(&mut push_opts))?;
-                info!("Done!");
-                Ok(())
+            Ok(repo) => Ok(repo),
+            Err(_e) => {
+                // a clone which was interrupted
+                if self.repo_path.exists() {
+                    fs::remove_dir_all(&self.repo_path)?;
+                }
+                self.clone_repo()
+            }
+        }
+    }
+
+    /// Fetches the upstream and brings the main branch up to date with it. Local
+    /// commits which have not been pushed yet are rebased onto the upstream.
+    /// Returns the upstream commits which were integrated, newest first.
+    pub fn fetch_upstream<'a>(
+        &self,
+        repo: &'a Repository,
+    ) -> Result<Vec<Commit<'a>>, error::Error> {
+        let mut fetch_opts = FetchOptions::new();
+        fetch_opts.remote_callbacks(self.gen_callbacks());
+        let refspec = format!("+refs/heads/*:refs/remotes/{}/*", self.remote_name);
+        let previous = repo.refname_to_id(&self.upstream_branch()).ok();
+        self.remote(repo)?
+            .fetch(&[refspec], Some(&mut fetch_opts), None)?;
+
+        let upstream = match repo.refname_to_id(&self.upstream_branch()) {
+            Ok(upstream) => upstream,
+            // nothing has been pushed yet
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let local = repo.refname_to_id(MAIN_BRANCH).ok();
+        if let Err(e) = self.verify_fetched(repo, upstream, previous, local) {
+            // the next fetch must not take the refused commits as verified
+            match previous {
+                Some(previous) => {
+                    repo.reference(&self.upstream_branch(), previous, true, "setman: refuse")?;
+                }
+                None => repo.find_reference(&self.upstream_branch())?.delete()?,
+            }
+            return Err(e);
+        }
+        let local = local.unwrap_or(upstream);
+        let base = match local == upstream {
+            true => local,
+            false => repo.merge_base(local, upstream)?,
+        };
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(upstream)?;
+        revwalk.hide(base)?;
+        let integrated = revwalk
+            .map(|oid| repo.find_commit(oid?))
+            .collect::<Result<Vec<Commit>, Error>>()?;
+
+        let head = match base == local {
+            true => upstream,
+            false => self.rebase(repo, base, local, upstream)?,
+        };
+        repo.reference(MAIN_BRANCH, head, true, "setman: update from upstream")?;
+        repo.set_head(MAIN_BRANCH)?;
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+        Ok(integrated)
+    }
+
+    // checks the signatures of the upstream commits which are new to the clone, those
+    // reachable from `upstream` but not from the upstream as previously fetched or the
+    // local branch. A fresh clone has nothing to go by, only its head is checked then
+    fn verify_fetched(
+        &self,
+        repo: &Repository,
+        upstream: Oid,
+        previous: Option<Oid>,
+        local: Option<Oid>,
+    ) -> Result<(), error::Error> {
+        let signers = match &self.signers {
+            Some(signers) => signers,
+            None => return Ok(()),
+        };
+        let known: Vec<Oid> = previous.iter().chain(local.iter()).cloned().collect();
+        if known.is_empty() {
+            return signing::verify_commit(repo, upstream, signers);
+        }
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(upstream)?;
+        for oid in known {
+            revwalk.hide(oid)?;
+        }
+        for oid in revwalk {
+            signing::verify_commit(repo, oid?, signers)?;
+        }
+        Ok(())
+    }
+
+    // replays the commits between `base` and `local` onto `upstream` and returns
+    // the new head. Commits whose changes are upstream already are dropped
+    fn rebase(
+        &self,
+        repo: &Repository,
+        base: Oid,
+        local: Oid,
+        upstream: Oid,
+    ) -> Result<Oid, error::Error> {
+        let mut revwalk = repo.revwalk()?;
+        revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        revwalk.push(local)?;
+        revwalk.hide(base)?;
+
+        // like git, the rebased commits are committed (and signed) by the current user
+        let committer = repo.signature()?;
+        let mut head = repo.find_commit(upstream)?;
+        for oid in revwalk {
+            let commit = repo.find_commit(oid?)?;
+            let mut index = repo.cherrypick_commit(&commit, &head, 0, None)?;
+            if index.has_conflicts() {
+                let files = index
+                    .conflicts()?
+                    .filter_map(|conflict| conflict.ok())
+                    .filter_map(|conflict| conflict.our.or(conflict.their))
+                    .map(|entry| String::from_utf8_lossy(&entry.path).to_string())
+                    .collect();
+                return Err(error::Error::Conflict(
+                    commit.id().to_string(),
+                    files,
+                    self.repo_path.clone(),
+                ));
+            }
+            let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+            if tree.id() == head.tree_id() {
+                continue;
+            }
+            let message = commit.message().unwrap_or_default();
+            let new_commit_id =
+                self.write_commit(repo, &commit.author(), &committer, message, &tree, &[&head])?;
+            head = repo.find_commit(new_commit_id)?;
+        }
+        Ok(head.id())
+    }
+
+    /// Commits everything in the clone to the main branch without pushing it.
+    /// Returns the new commit's id, or the current one if nothing changed.
+    pub fn commit_changes(&self, repo: &Repository, commit_msg: &str) -> Result<Oid, error::Error> {
+        let signature = repo.signature()?;
+        let mut index = repo.index()?;
+
+        // git add --all .
+        index.add_all(["."].iter(), IndexAddOption::DEFAULT, None)?;
+        index.update_all(["."].iter(), None)?;
+        index.write()?;
+
+        // get index tree
+        let tree_id = index.write_tree()?;
+        let tree = repo.find_tree(tree_id)?;
+
+        // an empty upstream has no main branch yet, its first commit has no parent
+        let parent = match self.get_parent_commit(repo) {
+            Ok(parent) => Some(parent),
+            Err(e) if e.code() == ErrorCode::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(parent) = &parent {
+            if parent.tree_id() == tree_id {
+                return Ok(parent.id());
             }
-            Err(e) => panic!("{}", GitError::RepoOpen(&self.repo_path, e)),
         }
+        self.create_commit(repo, &signature, &tree, parent.as_ref(), commit_msg)
+    }
+
+    /// Pushes the main branch, delivering all commits which are not upstream yet.
+    /// Fails with `ErrorCode::NotFastForward` if the upstream has moved on.
+    pub fn push_commits(&self, repo: &Repository) -> Result<(), Error> {
+        self.push_main(repo, MAIN_BRANCH)
+    }
+
+    /// Force pushes the main branch, making the upstream a mirror of the clone.
+    pub fn mirror_commits(&self, repo: &Repository) -> Result<(), Error> {
+        self.push_main(repo, &format!("+{}", MAIN_BRANCH))
+    }
+
+    fn push_main(&self, repo: &Repository, refspec: &str) -> Result<(), Error> {
+        let rejection: RefCell<Option<String>> = RefCell::new(None);
+        let mut callbacks = self.gen_callbacks();
+        callbacks.push_update_reference(|_refname, status| {
+            *rejection.borrow_mut() = status.map(String::from);
+            Ok(())
+        });
+        let mut push_opts = PushOptions::new();
+        push_opts.remote_callbacks(callbacks);
+
+        self.remote(repo)?.push(&[refspec], Some(&mut push_opts))?;
+        let rejected = rejection.borrow_mut().take();
+        if let Some(status) = rejected {
+            return Err(Error::new(
+                ErrorCode::NotFastForward,
+                ErrorClass::Reference,
+                format!("The upstream rejected the push: {}", status),
+            ));
+        }
+        let local = repo.refname_to_id(MAIN_BRANCH)?;
+        repo.reference(&self.upstream_branch(), local, true, "setman: push")?;
+        Ok(())
+    }
+
+    /// Commits on the main branch which have not been pushed yet, newest first.
+    pub fn pending_commits<'a>(&self, repo: &'a Repository) -> Result<Vec<Commit<'a>>, Error> {
+        let mut revwalk = repo.revwalk()?;
+        match revwalk.push_ref(MAIN_BRANCH) {
+            // nothing was committed to a clone of an empty upstream yet
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(vec![]),
+            result => result?,
+        }
+        if let Ok(upstream) = repo.refname_to_id(&self.upstream_branch()) {
+            revwalk.hide(upstream)?;
+        }
+        revwalk.map(|oid| repo.find_commit(oid?)).collect()
     }
 
     fn create_commit(
@@ -122,33 +340,53 @@ impl GitRepo {
         repo: &Repository,
         signature: &Signature,
         tree: &Tree,
-        parent: &Commit,
-    ) -> Result<Oid, Error> {
-        let commit_msg = readline::read("Enter a commit message").unwrap();
+        parent: Option<&Commit>,
+        commit_msg: &str,
+    ) -> Result<Oid, error::Error> {
         let pretty_message = git2::message_prettify(commit_msg, None)?;
-        let new_commit_id: Oid = match repo.commit(
-            Some("HEAD"),
-            &signature,
-            &signature,
-            &pretty_message,
-            &tree,
-            &[parent],
-        ) {
-            Ok(commit) => commit,
-            Err(e) => panic!("{}", GitError::CreateCommit(e)),
+        let parents: Vec<&Commit> = parent.into_iter().collect();
+        let commit_id =
+            self.write_commit(repo, signature, signature, &pretty_message, tree, &parents)?;
+        repo.reference(MAIN_BRANCH, commit_id, true, "setman: commit")?;
+        repo.set_head(MAIN_BRANCH)?;
+        Ok(commit_id)
+    }
+
+    // creates a commit without updating any reference. It is signed if git is
+    // configured to sign commits, see `signing::sign_commit`
+    fn write_commit(
+        &self,
+        repo: &Repository,
+        author: &Signature,
+        committer: &Signature,
+        message: &str,
+        tree: &Tree,
+        parents: &[&Commit],
+    ) -> Result<Oid, error::Error> {
+        let buffer = repo.commit_create_buffer(author, committer, message, tree, parents)?;
+        let content = match buffer.as_str() {
+            Some(content) => content,
+            None => return Err(Error::from_str("The commit is not valid UTF-8").into()),
         };
-        info!("Created new commit with id: {}", new_commit_id);
-        Ok(new_commit_id)
+        match signing::sign_commit(&repo.config()?, content, committer)? {
+            Some(signature) => Ok(repo.commit_signed(content, &signature, Some("gpgsig"))?),
+            None => Ok(repo.commit(None, author, committer, message, tree, parents)?),
+        }
     }
 
-    fn save_commit_id(&self, commit_id: Oid) -> std::io::Result<()> {
-        job!("Saving new commit id");
-        let commit_id_path = Paths::default().commit_id_path;
-        let file = File::create(commit_id_path)?;
-        let mut file = LineWriter::new(file);
-        file.write_all(commit_id.to_string().as_bytes())?;
-        file.flush()?;
-        Ok(())
+    // the upstream's remote in the clone, added or pointed at the url if needed
+    fn remote<'a>(&self, repo: &'a Repository) -> Result<Remote<'a>, Error> {
+        match repo.find_remote(&self.remote_name) {
+            Ok(remote) if remote.url() == Some(self.upstream_url.as_str()) => Ok(remote),
+            Ok(_remote) => {
+                repo.remote_set_url(&self.remote_name, &self.upstream_url)?;
+                repo.find_remote(&self.remote_name)
+            }
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                repo.remote(&self.remote_name, &self.upstream_url)
+            }
+            Err(e) => Err(e),
+        }
     }
 
     fn gen_callbacks(&'_ self) -> RemoteCallbacks<'_> {
@@ -159,34 +397,178 @@ impl GitRepo {
         callbacks
     }
 
-    pub fn get_parent_commit<'a>(&self, repo: &'a Repository) -> Option<Commit<'a>> {
-        let commit = match repo.revparse_single("origin") {
-            Ok(obj) => obj,
-            Err(e) => panic!("{}", GitError::RevParseError(e)),
+    pub fn get_parent_commit<'a>(&self, repo: &'a Repository) -> Result<Commit<'a>, Error> {
+        repo.find_reference(MAIN_BRANCH)?.peel_to_commit()
+    }
+
+    /// Resolves `at` to a commit. `at` can be anything git can parse as a revision
+    /// (commit id, tag, branch) or a date formatted as `YYYY-MM-DD [HH:MM]` (UTC),
+    /// in which case the latest commit made at or before that date is used.
+    pub fn find_commit<'a>(&self, repo: &'a Repository, at: &str) -> Result<Commit<'a>, Error> {
+        if let Ok(obj) = repo.revparse_single(at) {
+            return obj.peel_to_commit();
         }
-        .as_commit()
-        .unwrap()
-        .to_owned();
-        Some(commit)
+        let timestamp = match parse_date(at) {
+            Some(timestamp) => timestamp,
+            None => {
+                return Err(Error::from_str(&format!(
+                    "'{}' is neither a revision nor a date",
+                    at
+                )))
+            }
+        };
+
+        let head = self.get_parent_commit(repo)?;
+        let mut revwalk = repo.revwalk()?;
+        revwalk.set_sorting(Sort::TIME)?;
+        revwalk.push(head.id())?;
+        for oid in revwalk {
+            let commit = repo.find_commit(oid?)?;
+            if commit.time().seconds() <= timestamp {
+                return Ok(commit);
+            }
+        }
+        Err(Error::from_str(&format!(
+            "No commit found at or before {}",
+            at
+        )))
     }
 
-    pub fn clone_repo(&self, save_commit_id: bool) {
-        job!("Cloning down from upstream");
+    /// Writes the files of `dir_name` as they were in `commit` to `dest`.
+    /// Returns the names of the restored files.
+    pub fn restore_dir(
+        &self,
+        repo: &Repository,
+        commit: &Commit,
+        dir_name: &str,
+        dest: &Path,
+    ) -> Result<Vec<String>, error::Error> {
+        let tree = commit.tree()?;
+        let entry = match tree.get_path(Path::new(dir_name)) {
+            Ok(entry) => entry,
+            Err(_e) => {
+                return Err(Error::from_str(&format!(
+                    "{} does not exist in commit {}",
+                    dir_name,
+                    commit.id()
+                ))
+                .into())
+            }
+        };
+        let dir_tree = entry.to_object(repo)?.peel_to_tree()?;
+
+        if !dest.exists() {
+            fs::create_dir_all(dest)?;
+        }
 
-        let callbacks = self.gen_callbacks();
-        let mut fetch_opts = FetchOptions::new();
-        fetch_opts.remote_callbacks(callbacks);
+        let mut restored: Vec<String> = Vec::new();
+        for entry in dir_tree.iter() {
+            if entry.kind() != Some(ObjectType::Blob) {
+                continue;
+            }
+            let file_name = entry.name().unwrap().to_string();
+            let blob = entry.to_object(repo)?.peel_to_blob()?;
+            let mut dest_path = dest.to_path_buf();
+            dest_path.push(&file_name);
+            fileman::write_atomic(&dest_path, blob.content())?;
+            restored.push(file_name);
+        }
+        Ok(restored)
+    }
 
-        let mut builder = RepoBuilder::new();
-        builder.fetch_options(fetch_opts);
+    /// Clones the upstream into `repo_path`. The upstream is fetched like by
+    /// `fetch_upstream`, so nothing is checked out before it is verified.
+    pub fn clone_repo(&self) -> Result<Repository, error::Error> {
+        let repo = Repository::init(&self.repo_path)?;
+        let fetched = self.fetch_upstream(&repo).map(|_integrated| ());
+        if let Err(e) = fetched {
+            drop(repo);
+            let _ = fs::remove_dir_all(&self.repo_path);
+            return Err(e);
+        }
+        Ok(repo)
+    }
+}
+
+// parses `YYYY-MM-DD` or `YYYY-MM-DD HH:MM` (UTC) into a unix timestamp.
+// a date without a time refers to the end of that day.
+fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.trim().splitn(2, ' ');
+    let ymd: Vec<i64> = parts
+        .next()?
+        .split('-')
+        .map(|p| p.parse::<i64>())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    if ymd.len() != 3 || !(1..=12).contains(&ymd[1]) || !(1..=31).contains(&ymd[2]) {
+        return None;
+    }
+    let seconds_of_day = match parts.next() {
+        Some(time) => {
+            let hm: Vec<i64> = time
+                .split(':')
+                .map(|p| p.parse::<i64>())
+                .collect::<Result<_, _>>()
+                .ok()?;
+            if hm.len() != 2 || !(0..24).contains(&hm[0]) || !(0..60).contains(&hm[1]) {
+                return None;
+            }
+            hm[0] * 3600 + hm[1] * 60
+        }
+        None => 24 * 3600 - 1,
+    };
 
-        let repo = builder
-            .clone(&self.upstream_url, Path::new(&self.repo_path))
-            .unwrap();
+    // days since the unix epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let (year, month, day) = (ymd[0], ymd[1], ymd[2]);
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
 
-        let latest_commit = self.clone().get_parent_commit(&repo).unwrap();
-        if save_commit_id {
-            self.save_commit_id(latest_commit.id()).unwrap();
+    Some(days * 86400 + seconds_of_day)
+}
+
+/// Opens the bare repository at `path`, None if there is no bare repository.
+pub fn open_bare(path: &Path) -> Option<Repository> {
+    Repository::open_bare(path)
+        .ok()
+        .filter(|repo| repo.is_bare())
+}
+
+/// Writes the files of the tree `HEAD` points to into `dest`, like a checkout
+/// without a `.git` directory. Symlinks and submodules are left out.
+pub fn export_head(repo: &Repository, dest: &Path) -> Result<(), error::Error> {
+    let tree = repo.head()?.peel_to_tree()?;
+    let mut blobs: Vec<(PathBuf, Oid)> = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) && entry.filemode() != 0o120000 {
+            let name = entry.name().unwrap_or_default();
+            blobs.push((Path::new(dir).join(name), entry.id()));
         }
+        TreeWalkResult::Ok
+    })?;
+    for (path, oid) in blobs {
+        let dest_path = dest.join(path);
+        fs::create_dir_all(dest_path.parent().unwrap())?;
+        fileman::write_atomic(&dest_path, repo.find_blob(oid)?.content())?;
     }
+    Ok(())
+}
+
+/// Clones `url` into `path` using the default git config for credentials.
+pub fn clone_url(url: &str, path: &Path) -> Result<Repository, Error> {
+    let git_config = Config::open_default()?;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |_str, _option, _cred_type| {
+        Cred::credential_helper(&git_config, url, None)
+    });
+    let mut fetch_opts = FetchOptions::new();
+    fetch_opts.remote_callbacks(callbacks);
+
+    RepoBuilder::new()
+        .fetch_options(fetch_opts)
+        .clone(url, path)
 }
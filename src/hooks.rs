@@ -0,0 +1,169 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::fileman::App;
+use crate::paths;
+use crate::thiserror;
+
+use paths::Paths;
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use thiserror::Error;
+
+/// Shell commands to run before and after actions on an application.
+/// Declared in `apps.toml` as an `[items.hooks]` table.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_install: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_install: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_uninstall: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_uninstall: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_save: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_save: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_pull: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_pull: Option<String>,
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+}
+
+/// What to do when a hook exits with a non-zero status.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Stop setman
+    #[default]
+    Abort,
+    /// Skip the action for the application if a pre hook fails
+    Skip,
+    /// Print a warning and carry on
+    Warn,
+    /// Carry on silently
+    Ignore,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum HookEvent {
+    PreInstall,
+    PostInstall,
+    PreUninstall,
+    PostUninstall,
+    PreSave,
+    PostSave,
+    PrePull,
+    PostPull,
+}
+
+/// How a hook which was run ended.
+#[derive(Debug)]
+pub enum HookOutcome {
+    Succeeded,
+    /// The hook failed but the failure policy lets the action go on
+    Failed(HookError),
+    /// The hook failed and the failure policy is to ignore it
+    Ignored(HookError),
+    /// A pre hook failed and the action is skipped for the application
+    Skipped(HookError),
+}
+
+#[derive(Error, Debug)]
+pub enum HookError {
+    #[error("Failed to run {0} hook for {1}: {2}")]
+    Spawn(&'static str, String, std::io::Error),
+    #[error("{0} hook for {1} exited with {2}")]
+    Failed(&'static str, String, std::process::ExitStatus),
+}
+
+impl HookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::PreInstall => "pre_install",
+            HookEvent::PostInstall => "post_install",
+            HookEvent::PreUninstall => "pre_uninstall",
+            HookEvent::PostUninstall => "post_uninstall",
+            HookEvent::PreSave => "pre_save",
+            HookEvent::PostSave => "post_save",
+            HookEvent::PrePull => "pre_pull",
+            HookEvent::PostPull => "post_pull",
+        }
+    }
+
+    fn action(&self) -> &'static str {
+        self.name().split_once('_').unwrap().1
+    }
+
+    fn is_pre(&self) -> bool {
+        self.name().starts_with("pre_")
+    }
+
+    fn command<'a>(&self, hooks: &'a Hooks) -> Option<&'a String> {
+        match self {
+            HookEvent::PreInstall => hooks.pre_install.as_ref(),
+            HookEvent::PostInstall => hooks.post_install.as_ref(),
+            HookEvent::PreUninstall => hooks.pre_uninstall.as_ref(),
+            HookEvent::PostUninstall => hooks.post_uninstall.as_ref(),
+            HookEvent::PreSave => hooks.pre_save.as_ref(),
+            HookEvent::PostSave => hooks.post_save.as_ref(),
+            HookEvent::PrePull => hooks.pre_pull.as_ref(),
+            HookEvent::PostPull => hooks.post_pull.as_ref(),
+        }
+    }
+}
+
+/// Runs the app's hook for `event`, if it has one, and applies the app's failure policy.
+/// Returns None if there is no hook and an error if the policy is to abort.
+///
+/// The hook runs through `sh -c` with these environment variables set:
+/// `SETMAN_APP`, `SETMAN_ACTION`, `SETMAN_HOOK`, `SETMAN_CONFIG_PATH`,
+/// `SETMAN_SETTINGS_PATH`, `SETMAN_FILES` and `SETMAN_CHANGED_FILES`
+/// (file lists are space separated).
+pub fn run_hook(
+    app: &App,
+    event: HookEvent,
+    changed_files: &[String],
+) -> Result<Option<HookOutcome>, HookError> {
+    let hooks = match &app.hooks {
+        Some(hooks) => hooks,
+        None => return Ok(None),
+    };
+    let command = match event.command(hooks) {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+
+    let mut settings_path = Paths::default().settings_path;
+    settings_path.push(&app.name);
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("SETMAN_APP", &app.name)
+        .env("SETMAN_ACTION", event.action())
+        .env("SETMAN_HOOK", event.name())
+        .env("SETMAN_CONFIG_PATH", &app.config_path)
+        .env("SETMAN_SETTINGS_PATH", &settings_path)
+        .env("SETMAN_FILES", app.file_names.join(" "))
+        .env("SETMAN_CHANGED_FILES", changed_files.join(" "))
+        .status();
+
+    let error = match status {
+        Ok(status) if status.success() => return Ok(Some(HookOutcome::Succeeded)),
+        Ok(status) => HookError::Failed(event.name(), app.name.clone(), status),
+        Err(e) => HookError::Spawn(event.name(), app.name.clone(), e),
+    };
+    let outcome = match hooks.on_failure {
+        FailurePolicy::Abort => return Err(error),
+        FailurePolicy::Skip if event.is_pre() => HookOutcome::Skipped(error),
+        FailurePolicy::Skip | FailurePolicy::Warn => HookOutcome::Failed(error),
+        FailurePolicy::Ignore => HookOutcome::Ignored(error),
+    };
+    Ok(Some(outcome))
+}
@@ -0,0 +1,207 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::error::Error;
+use crate::fileman;
+use crate::paths;
+use crate::report::Warning;
+
+use fileman::{App, Apps};
+use git2::{ObjectType, Oid};
+use paths::Paths;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+
+/// Size limit for tracked files unless an app sets `max_file_size`
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Directory next to the apps in the published settings, holding the objects the
+/// pointers refer to so other machines can resolve them
+pub const OBJECTS_DIR_NAME: &str = ".setman-objects";
+
+// first line of a pointer file standing in for a large file in the repo
+const POINTER_HEADER: &str = "setman-pointer v1";
+
+// git looks at the same amount of bytes when guessing if a file is binary
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// What to do with files larger than an app's size limit.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LargeFilePolicy {
+    /// Save and push them anyway
+    Warn,
+    /// Leave them out of the store
+    #[default]
+    Skip,
+    /// Keep them in the side store and only push a pointer to them
+    Store,
+}
+
+impl LargeFilePolicy {
+    pub fn is_default(&self) -> bool {
+        *self == LargeFilePolicy::default()
+    }
+}
+
+pub struct Pointer {
+    pub oid: Oid,
+    pub size: u64,
+}
+
+/// Directory of the content-addressed side store for large files.
+pub fn object_store(apps: &Apps) -> PathBuf {
+    match &apps.large_file_store {
+        Some(path) => path.clone(),
+        None => Paths::default().objects_path,
+    }
+}
+
+pub fn max_file_size(app: &App) -> u64 {
+    app.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE)
+}
+
+/// Returns whether the file at `path` exceeds the app's size limit.
+pub fn is_large(app: &App, path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.len() > max_file_size(app),
+        Err(_e) => false,
+    }
+}
+
+/// Returns the files which should be saved according to the app's policy,
+/// along with a warning for each file in the app's config path exceeding its size limit.
+pub fn filter_large_files(app: &App, file_names: &[String]) -> (Vec<String>, Vec<Warning>) {
+    let mut warnings: Vec<Warning> = Vec::new();
+    let file_names = file_names
+        .iter()
+        .filter(|file| {
+            let size = match fs::metadata(app.config_path.join(file)) {
+                Ok(metadata) if metadata.len() > max_file_size(app) => metadata.len(),
+                _ => return true,
+            };
+            warnings.push(Warning::LargeFile {
+                file: file.to_string(),
+                size,
+                limit: max_file_size(app),
+                policy: app.large_files,
+            });
+            app.large_files != LargeFilePolicy::Skip
+        })
+        .cloned()
+        .collect();
+    (file_names, warnings)
+}
+
+/// Moves the content of `path` into the side store and replaces it with a pointer.
+pub fn replace_with_pointer(store: &Path, path: &Path) -> Result<Pointer, Error> {
+    let oid = Oid::hash_file(ObjectType::Blob, path)?;
+    let size = fs::metadata(path)?.len();
+    let object_path = store.join(oid.to_string());
+    if !object_path.exists() {
+        fs::create_dir_all(store)?;
+        fileman::copy_atomic(path, &object_path)?;
+    }
+    let pointer = format!("{}\noid {}\nsize {}\n", POINTER_HEADER, oid, size);
+    fileman::write_atomic(path, pointer)?;
+    Ok(Pointer { oid, size })
+}
+
+/// Reads the pointer in `path`, returns None if it is a regular file.
+pub fn read_pointer(path: &Path) -> Option<Pointer> {
+    // pointers are tiny, don't read large files into memory
+    if fs::metadata(path).ok()?.len() > 256 {
+        return None;
+    }
+    let content = fs::read_to_string(path).ok()?;
+    let mut lines = content.lines();
+    if lines.next()? != POINTER_HEADER {
+        return None;
+    }
+    let oid = Oid::from_str(lines.next()?.strip_prefix("oid ")?).ok()?;
+    let size = lines.next()?.strip_prefix("size ")?.parse::<u64>().ok()?;
+    Some(Pointer { oid, size })
+}
+
+/// Copies the object `oid` from the store at `from` to the store at `to`, unless `to`
+/// has it already. Returns false if neither has it. Objects are checked against their
+/// id, so a pointer in a verified revision can't be resolved to other content.
+pub fn copy_object(from: &Path, to: &Path, oid: Oid) -> Result<bool, Error> {
+    let dest = to.join(oid.to_string());
+    if dest.exists() {
+        return Ok(true);
+    }
+    let source = from.join(oid.to_string());
+    if !source.exists() {
+        return Ok(false);
+    }
+    if Oid::hash_file(ObjectType::Blob, &source)? != oid {
+        return Err(Error::CorruptObject(source));
+    }
+    fs::create_dir_all(to)?;
+    fileman::copy_atomic(&source, &dest)?;
+    Ok(true)
+}
+
+/// Copies the object for `pointer` from the side store to `dest`.
+/// Returns false if the side store does not have it.
+pub fn fetch_object(store: &Path, pointer: &Pointer, dest: &Path) -> IOResult<bool> {
+    let object_path = store.join(pointer.oid.to_string());
+    if !object_path.exists() {
+        return Ok(false);
+    }
+    fileman::copy_atomic(&object_path, dest)?;
+    Ok(true)
+}
+
+/// Guesses whether `content` is binary the same way git does, by looking for a NUL byte.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(BINARY_CHECK_LEN).any(|b| *b == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::with_home;
+
+    #[test]
+    fn large_files_are_skipped_by_default() {
+        with_home(|home| {
+            let file_names = vec!["small".to_string(), "large".to_string()];
+            let mut app = App::new("app".to_string(), "app".to_string(), file_names.clone());
+            app.max_file_size = Some(5);
+            fs::create_dir_all(home.join("app")).unwrap();
+            fs::write(home.join("app/small"), "small").unwrap();
+            fs::write(home.join("app/large"), "large file").unwrap();
+
+            let (saved, warnings) = filter_large_files(&app, &file_names);
+            assert_eq!(saved, vec!["small".to_string()]);
+            assert_eq!(warnings.len(), 1);
+        })
+    }
+
+    #[test]
+    fn objects_are_checked_against_their_id() {
+        with_home(|home| {
+            let (published, store) = (home.join("published"), home.join("store"));
+            let oid = Oid::hash_object(ObjectType::Blob, b"large file").unwrap();
+            assert!(!copy_object(&published, &store, oid).unwrap());
+
+            fs::create_dir_all(&published).unwrap();
+            fs::write(published.join(oid.to_string()), "other content").unwrap();
+            match copy_object(&published, &store, oid) {
+                Err(Error::CorruptObject(_path)) => (),
+                result => panic!("expected a corrupt object, got {:?}", result),
+            }
+            assert!(!store.join(oid.to_string()).exists());
+
+            fs::write(published.join(oid.to_string()), "large file").unwrap();
+            assert!(copy_object(&published, &store, oid).unwrap());
+            let content = fs::read_to_string(store.join(oid.to_string())).unwrap();
+            assert_eq!(content, "large file");
+        })
+    }
+}
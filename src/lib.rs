@@ -0,0 +1,51 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+//! Application settings manager.
+//!
+//! The application list is read with [`Apps::load`], the local copies of each
+//! application's files are handled by a [`Store`] and syncing them by a
+//! [`SyncEngine`] on top of a git, directory or tarball backend. Nothing in this
+//! crate prints or prompts, every operation returns what it did so the caller can
+//! decide how to present it.
+
+extern crate filetime;
+extern crate flate2;
+extern crate fs2;
+extern crate git2;
+extern crate home;
+extern crate regex;
+extern crate serde;
+extern crate sha2;
+extern crate strsim;
+extern crate tar;
+extern crate thiserror;
+extern crate toml;
+extern crate uuid;
+
+pub mod archive;
+pub mod backend;
+pub mod config;
+pub mod dotfiles;
+mod error;
+pub mod fileman;
+pub mod gitman;
+pub mod hooks;
+pub mod largefiles;
+pub mod lock;
+pub mod meta;
+pub mod paths;
+pub mod report;
+pub mod signing;
+pub mod store;
+pub mod sync;
+
+#[cfg(test)]
+mod testing;
+
+pub use error::Error;
+pub use fileman::{App, Apps};
+pub use report::{Report, Warning};
+pub use store::Store;
+pub use sync::SyncEngine;
@@ -0,0 +1,65 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::paths;
+use crate::thiserror;
+
+use fs2::FileExt;
+use paths::Paths;
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Advisory lock on the setman directory, released when dropped.
+pub struct SetManLock {
+    _file: Option<File>,
+}
+
+#[derive(Error, Debug)]
+pub enum LockError {
+    #[error("Another setman is running (lock held on {0:?})")]
+    Held(PathBuf),
+    #[error("Failed to lock {0:?}: {1}")]
+    Io(PathBuf, std::io::Error),
+}
+
+impl SetManLock {
+    /// Takes the lock, failing if another setman holds it.
+    pub fn acquire() -> Result<SetManLock, LockError> {
+        SetManLock::lock(false)
+    }
+
+    /// Takes the lock, waiting for other setman processes to release it.
+    pub fn wait() -> Result<SetManLock, LockError> {
+        SetManLock::lock(true)
+    }
+
+    fn lock(wait: bool) -> Result<SetManLock, LockError> {
+        let paths = Paths::default();
+        // nothing to protect before `setman init` has created the directory
+        if !paths.setman_path.exists() {
+            return Ok(SetManLock { _file: None });
+        }
+        let file = match OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&paths.lock_path)
+        {
+            Ok(file) => file,
+            Err(e) => return Err(LockError::Io(paths.lock_path, e)),
+        };
+        let result = match wait {
+            true => file.lock_exclusive(),
+            false => file.try_lock_exclusive(),
+        };
+        match result {
+            Ok(()) => Ok(SetManLock { _file: Some(file) }),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                Err(LockError::Held(paths.lock_path))
+            }
+            Err(e) => Err(LockError::Io(paths.lock_path, e)),
+        }
+    }
+}
@@ -68,6 +68,14 @@ macro_rules! print_app {
                 "Config path =>".bold().cyan(),
                 app.config_path
             );
+            if !app.tags.is_empty() {
+                println!(
+                    "{}{} {}",
+                    " ".repeat(4),
+                    "Tags =>".bold().cyan(),
+                    app.tags.join(", ")
+                );
+            }
             if &app.file_names.len() > &0 {
                 println!("{}{}", " ".repeat(4), "File names:".bold().green());
                 for file in &app.file_names {
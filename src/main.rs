@@ -4,11 +4,11 @@
 
 extern crate clap;
 extern crate colored;
+extern crate console;
 extern crate dialoguer;
 extern crate git2;
-extern crate home;
-extern crate regex;
-extern crate serde;
+extern crate notify;
+extern crate setman;
 extern crate thiserror;
 extern crate toml;
 extern crate uuid;
@@ -16,15 +16,16 @@ extern crate uuid;
 #[macro_use]
 mod logger;
 mod args;
-mod fileman;
-mod gitman;
-mod paths;
+mod cli;
+mod completions;
+mod doctor;
 mod readline;
-mod setman;
 
-use clap::Values;
-use setman::ListOptions;
-use setman::SetManAction;
+use clap::{ArgMatches, Values};
+use cli::AppSelection;
+use cli::{SetManAction, SetManError};
+use setman::lock;
+use std::time::Duration;
 
 //hej jag heter ellen. jag älskar dig även fast du tycker jag är jobbig. glad smiley
 
@@ -35,96 +36,202 @@ pub enum LogLevel {
 }
 
 fn main() {
-    logger::print_header();
+    let matches = args::parse_args();
+    // completion output is read by the shell, keep it clean
+    if !matches!(
+        matches.subcommand_name(),
+        Some("completions") | Some("__complete")
+    ) {
+        logger::print_header();
+    }
+    let profile = matches.value_of("profile");
+    // read-only commands and commands which lock by themselves don't take the lock here
+    let lock = match matches.subcommand_name() {
+        Some("list") | Some("diff") | Some("status") | Some("doctor") | Some("compare")
+        | Some("watch") | Some("init") | Some("completions") | Some("__complete") | None => None,
+        _ => match lock::SetManLock::acquire() {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                warn!("{}", e);
+                std::process::exit(1)
+            }
+        },
+    };
+    // only commands holding the lock write a migrated application list
+    if let Some(lock) = &lock {
+        cli::migrate_apps(lock);
+    }
 
-    match args::parse_args().subcommand() {
+    match matches.subcommand() {
+        ("init", Some(sub_m)) => {
+            cli::init(
+                sub_m.value_of("upstream"),
+                sub_m.is_present("pull"),
+                sub_m.is_present("install"),
+                profile,
+            );
+        }
         ("list", Some(sub_m)) => {
             let verbose = matches!(sub_m.subcommand(), ("verbose", Some(_s)));
 
-            let regex = match sub_m.is_present("regex") {
-                true => Some(sub_m.value_of("regex").unwrap()),
-                false => None,
-            };
-            if regex.ne(&None) {
-                setman::print_app_list(ListOptions::Regex(regex.unwrap()), verbose);
-                return;
-            }
-
-            let app_names = match sub_m.is_present("app") {
-                true => {
-                    let values = sub_m.values_of("app").unwrap();
-                    Some(values.collect::<Vec<&str>>())
-                }
-                false => None,
-            };
-            setman::print_app_list(ListOptions::Literal(&app_names), verbose);
+            cli::print_app_list(app_selection_or_all(sub_m, "app"), verbose);
         }
         ("install", Some(sub_m)) => match sub_m.subcommand() {
             ("app", Some(app_subcommand)) => {
-                setman::app_action(SetManAction::Install(
-                    app_subcommand.value_of("application").unwrap(),
-                ));
+                let selection = app_selection(app_subcommand, "application");
+                cli::app_action(SetManAction::Install(selection));
             }
             ("all", Some(all_subcommand)) => {
                 job!("Installing all applications");
-                setman::all_apps_action(SetManAction::InstallAll(&get_skipped_apps(
-                    all_subcommand.values_of("skip"),
-                )))
+                let skip = get_values(all_subcommand.values_of("skip"));
+                cli::app_action(SetManAction::Install(AppSelection::All(skip)));
             }
             _ => {}
         },
         ("uninstall", Some(sub_m)) => match sub_m.subcommand() {
             ("app", Some(app_subcommand)) => {
-                setman::app_action(SetManAction::Uninstall(
-                    app_subcommand.value_of("application").unwrap(),
-                ));
+                let selection = app_selection(app_subcommand, "application");
+                cli::app_action(SetManAction::Uninstall(selection));
             }
             ("all", Some(all_subcommand)) => {
                 job!("Uninstalling all applications");
-                setman::all_apps_action(SetManAction::UninstallAll(&get_skipped_apps(
-                    all_subcommand.values_of("skip"),
-                )));
+                let skip = get_values(all_subcommand.values_of("skip"));
+                cli::app_action(SetManAction::Uninstall(AppSelection::All(skip)));
             }
             _ => {}
         },
         ("save", Some(sub_m)) => {
             match sub_m.subcommand() {
                 ("app", Some(app_subcommand)) => {
-                    setman::app_action(SetManAction::Save(
-                        app_subcommand.value_of("application").unwrap(),
-                    ));
+                    let selection = app_selection(app_subcommand, "application");
+                    cli::app_action(SetManAction::Save(selection));
                 }
                 ("all", Some(all_subcommand)) => {
                     job!("Saving all applications");
-                    setman::all_apps_action(SetManAction::SaveAll(&get_skipped_apps(
-                        all_subcommand.values_of("skip"),
-                    )));
+                    let skip = get_values(all_subcommand.values_of("skip"));
+                    cli::app_action(SetManAction::Save(AppSelection::All(skip)));
                 }
                 _ => {}
             };
             if sub_m.is_present("push") {
-                setman::sync_settings(SetManAction::Push).unwrap();
+                cli::check(cli::sync_settings(SetManAction::Push, profile));
             }
         }
         ("modify", Some(sub_m)) => {
-            let app_name = sub_m.value_of("app").unwrap();
-            setman::app_action(SetManAction::Modify(&app_name));
+            for app_name in app_names(sub_m, "app", false) {
+                match sub_m.is_present("edit") {
+                    true => cli::app_action(SetManAction::Edit(&app_name)),
+                    false => cli::app_action(SetManAction::Modify(&app_name)),
+                }
+            }
         }
         ("remove", Some(sub_m)) => {
-            let app_name = sub_m.value_of("app").unwrap();
-            setman::app_action(SetManAction::Remove(&app_name));
+            cli::app_action(SetManAction::Remove(app_selection(sub_m, "app")));
         }
-        ("new", Some(_sub_m)) => setman::app_action(SetManAction::New),
-        ("push", Some(_sub_m)) => setman::sync_settings(SetManAction::Push).unwrap(),
-        ("pull", Some(_sub_m)) => setman::sync_settings(SetManAction::Pull).unwrap(),
+        ("new", Some(_sub_m)) => cli::app_action(SetManAction::New),
+        ("add", Some(sub_m)) => {
+            let file_paths = get_values(sub_m.values_of("path"));
+            cli::app_action(SetManAction::Add(
+                sub_m.value_of("app").unwrap(),
+                &file_paths,
+            ));
+        }
+        ("forget", Some(sub_m)) => {
+            let file_names = get_values(sub_m.values_of("file"));
+            cli::app_action(SetManAction::Forget(
+                sub_m.value_of("app").unwrap(),
+                &file_names,
+            ));
+        }
+        ("sync", Some(sub_m)) => cli::sync(
+            app_selection_or_all(sub_m, "app"),
+            sub_m.value_of("message").unwrap(),
+            profile,
+        ),
+        ("status", Some(_sub_m)) => cli::status(profile),
+        ("remote", Some(sub_m)) => match sub_m.subcommand() {
+            ("add", Some(add_m)) => cli::add_remote(
+                add_m.value_of("name").unwrap(),
+                add_m.value_of("url").unwrap(),
+            ),
+            ("remove", Some(remove_m)) => cli::remove_remote(remove_m.value_of("name").unwrap()),
+            _ => cli::list_remotes(),
+        },
+        ("push", Some(_sub_m)) => cli::check(cli::sync_settings(SetManAction::Push, profile)),
+        ("pull", Some(_sub_m)) => cli::check(cli::sync_settings(SetManAction::Pull, profile)),
+        ("watch", Some(sub_m)) => {
+            let push_interval = match sub_m.is_present("push") {
+                true => {
+                    let secs = sub_m.value_of("interval").unwrap();
+                    match secs.parse::<u64>() {
+                        Ok(secs) => Some(Duration::from_secs(secs)),
+                        Err(_e) => cli::fail(format!("Invalid interval: {}", secs)),
+                    }
+                }
+                false => None,
+            };
+            cli::watch(push_interval, profile);
+        }
+        ("diff", Some(sub_m)) => cli::diff_apps(app_selection(sub_m, "app")),
+        ("restore", Some(sub_m)) => {
+            for app_name in app_names(sub_m, "app", false) {
+                cli::restore_app(
+                    &app_name,
+                    sub_m.value_of("at").unwrap(),
+                    sub_m.is_present("install"),
+                    profile,
+                );
+            }
+        }
+        ("import", Some(sub_m)) => {
+            cli::import_dotfiles(sub_m.value_of("source").unwrap());
+        }
+        ("export", Some(sub_m)) => cli::export_archive(sub_m.value_of("file").unwrap()),
+        ("import-archive", Some(sub_m)) => {
+            let install = match sub_m.is_present("install") {
+                true => Some(get_values(sub_m.values_of("install"))),
+                false => None,
+            };
+            cli::import_archive(sub_m.value_of("file").unwrap(), install);
+        }
+        ("completions", Some(sub_m)) => completions::generate(sub_m.value_of("shell").unwrap()),
+        ("__complete", Some(sub_m)) => completions::list(sub_m.value_of("kind").unwrap()),
+        ("doctor", Some(_sub_m)) => doctor::run(profile),
         ("compare", Some(_sub_m)) => {
-            setman::compare_upstream();
+            cli::compare_upstream(profile);
         }
-        _ => panic!("Invalid option"),
+        _ => cli::fail(SetManError::InvalidOption),
+    }
+}
+
+// the app named by `arg`, or the apps the user picks if it was left out
+fn app_names(matches: &ArgMatches, arg: &str, multiple: bool) -> Vec<String> {
+    match matches.value_of(arg) {
+        Some(app_name) => vec![app_name.to_string()],
+        None => cli::pick_apps(multiple),
+    }
+}
+
+// the apps selected by the names in `arg`, `--regex` or `--tag`
+fn app_selection<'a>(matches: &'a ArgMatches, arg: &str) -> AppSelection<'a> {
+    match matches.value_of("regex") {
+        Some(regex) => AppSelection::Regex(regex),
+        None if matches.is_present("tag") => {
+            AppSelection::Tags(get_values(matches.values_of("tag")))
+        }
+        None => AppSelection::Names(get_values(matches.values_of(arg))),
+    }
+}
+
+// like `app_selection`, but selects every app instead of asking if none are named
+fn app_selection_or_all<'a>(matches: &'a ArgMatches, arg: &str) -> AppSelection<'a> {
+    match app_selection(matches, arg) {
+        AppSelection::Names(names) if names.is_empty() => AppSelection::All(vec![]),
+        selection => selection,
     }
 }
 
-fn get_skipped_apps(arg_values: Option<Values<'_>>) -> Vec<String> {
+fn get_values(arg_values: Option<Values<'_>>) -> Vec<String> {
     match arg_values {
         Some(app_names) => app_names
             .map(|names| names.to_string())
@@ -0,0 +1,184 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::fileman;
+use crate::fileman::TOMLError;
+use crate::report::Warning;
+
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Result as IOResult;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Name of the file in an app's settings directory holding file metadata.
+/// git only keeps track of the executable bit so modes are kept here instead.
+pub const META_FILE_NAME: &str = ".setman-meta.toml";
+
+/// Name of the file in the store root recording the directories installs created.
+/// Only app directories are synced, so it stays on this machine.
+pub const CREATED_DIRS_FILE_NAME: &str = ".setman-created.toml";
+
+// path components and file names which are likely to contain secrets
+const SECRET_DIRS: [&str; 4] = [".ssh", ".gnupg", ".aws", ".kube"];
+const SECRET_NAMES: [&str; 6] = [
+    "id_rsa",
+    "id_ed25519",
+    "credentials",
+    ".netrc",
+    ".pgpass",
+    ".git-credentials",
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AppMeta {
+    #[serde(default)]
+    pub files: BTreeMap<String, FileMeta>,
+}
+
+/// The directories installs created on this machine, by app name, deepest first.
+/// Uninstalling an app removes only these.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CreatedDirs {
+    #[serde(default)]
+    pub apps: BTreeMap<String, Vec<PathBuf>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileMeta {
+    /// Permission bits in octal, e.g. "600"
+    pub mode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+}
+
+impl AppMeta {
+    /// Reads the metadata in `dir`, returns empty metadata if there is none.
+    pub fn load(dir: &Path) -> Result<AppMeta, toml::de::Error> {
+        match fs::read_to_string(dir.join(META_FILE_NAME)) {
+            Ok(content) => toml::from_str::<AppMeta>(&content),
+            Err(_e) => Ok(AppMeta::default()),
+        }
+    }
+
+    /// Like `load` but falls back to empty metadata, with a warning, if it is invalid.
+    pub fn load_or_default(dir: &Path, warnings: &mut Vec<Warning>) -> AppMeta {
+        AppMeta::load(dir).unwrap_or_else(|e| {
+            warnings.push(Warning::InvalidMeta {
+                dir: dir.to_path_buf(),
+                message: e.to_string(),
+            });
+            AppMeta::default()
+        })
+    }
+
+    pub fn write(&self, dir: &Path) -> IOResult<()> {
+        let toml = toml::to_string(&self).unwrap();
+        fileman::write_atomic(&dir.join(META_FILE_NAME), toml)
+    }
+
+    /// Records the mode (and mtime if `with_mtime`) of `path` under `file_name`.
+    pub fn record(&mut self, file_name: &str, path: &Path, with_mtime: bool) -> IOResult<()> {
+        let metadata = fs::metadata(path)?;
+        let mode = metadata.permissions().mode() & 0o7777;
+        let mtime = match with_mtime {
+            true => Some(FileTime::from_last_modification_time(&metadata).unix_seconds()),
+            false => None,
+        };
+        self.files.insert(
+            file_name.to_string(),
+            FileMeta {
+                mode: format!("{:o}", mode),
+                mtime,
+            },
+        );
+        Ok(())
+    }
+
+    /// Drops metadata for files which are no longer tracked.
+    pub fn retain_files(&mut self, file_names: &[String]) {
+        self.files.retain(|name, _| file_names.contains(name));
+    }
+
+    /// Applies the recorded metadata for `file_name` to `path`, if there is any.
+    /// Only the permission bits of the mode are applied, a pulled file can't make
+    /// an installed file setuid, setgid or sticky. Returns a warning if the recorded
+    /// mode is invalid.
+    pub fn apply(&self, file_name: &str, path: &Path) -> IOResult<Option<Warning>> {
+        let file_meta = match self.files.get(file_name) {
+            Some(file_meta) => file_meta,
+            None => return Ok(None),
+        };
+        let mut warning = None;
+        match u32::from_str_radix(&file_meta.mode, 8) {
+            Ok(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))?,
+            Err(_e) => {
+                warning = Some(Warning::InvalidMode {
+                    file: file_name.to_string(),
+                    mode: file_meta.mode.clone(),
+                })
+            }
+        }
+        if let Some(mtime) = file_meta.mtime {
+            filetime::set_file_mtime(path, FileTime::from_unix_time(mtime, 0))?;
+        }
+        Ok(warning)
+    }
+}
+
+impl CreatedDirs {
+    /// Reads the record in `dir`, returns an empty one if there is none.
+    pub fn load(dir: &Path) -> Result<CreatedDirs, TOMLError> {
+        match fs::read_to_string(dir.join(CREATED_DIRS_FILE_NAME)) {
+            Ok(content) => Ok(toml::from_str::<CreatedDirs>(&content)?),
+            Err(_e) => Ok(CreatedDirs::default()),
+        }
+    }
+
+    pub fn write(&self, dir: &Path) -> IOResult<()> {
+        let toml = toml::to_string(&self).unwrap();
+        fileman::write_atomic(&dir.join(CREATED_DIRS_FILE_NAME), toml)
+    }
+
+    /// Adds `dirs` to the directories created for `app_name`.
+    pub fn record(&mut self, app_name: &str, dirs: Vec<PathBuf>) {
+        let created = self.apps.entry(app_name.to_string()).or_default();
+        for dir in dirs {
+            if !created.contains(&dir) {
+                created.push(dir);
+            }
+        }
+        // deepest first, so children are removed before their parents
+        created.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    }
+}
+
+/// Returns a warning if `path` looks like it holds secrets and can be read by other users.
+pub fn check_permissions(path: &Path) -> Option<Warning> {
+    let is_secret = path.components().any(|c| {
+        let c = c.as_os_str().to_str().unwrap_or_default();
+        SECRET_DIRS.contains(&c)
+    }) || match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => {
+            let lower = name.to_lowercase();
+            SECRET_NAMES.iter().any(|s| lower.starts_with(s))
+                || lower.contains("secret")
+                || lower.contains("token")
+        }
+        None => false,
+    };
+    if !is_secret {
+        return None;
+    }
+    let mode = fs::metadata(path).ok()?.permissions().mode() & 0o777;
+    match mode & 0o077 != 0 {
+        true => Some(Warning::Permissive {
+            path: path.to_path_buf(),
+            mode,
+        }),
+        false => None,
+    }
+}
@@ -2,9 +2,12 @@
 
 // SPDX-License-Identifier: BSD-2-Clause
 
-use std::path::PathBuf;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 
 use home::home_dir;
+use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct Paths {
@@ -13,6 +16,11 @@ pub struct Paths {
     pub applist_path: PathBuf,
     pub upstream_path: PathBuf,
     pub commit_id_path: PathBuf,
+    pub published_path: PathBuf,
+    pub lock_path: PathBuf,
+    pub objects_path: PathBuf,
+    pub config_path: PathBuf,
+    pub repos_path: PathBuf,
 }
 
 impl Default for Paths {
@@ -34,12 +42,32 @@ impl Default for Paths {
         let mut commit_id_path = setman_path.clone();
         commit_id_path.push("latest_commit");
 
+        let mut published_path = setman_path.clone();
+        published_path.push("published_files");
+
+        let mut lock_path = setman_path.clone();
+        lock_path.push(".lock");
+
+        let mut objects_path = setman_path.clone();
+        objects_path.push("objects");
+
+        let mut config_path = setman_path.clone();
+        config_path.push("config.toml");
+
+        let mut repos_path = setman_path.clone();
+        repos_path.push("repos");
+
         Paths {
             setman_path,
             settings_path,
             applist_path,
             upstream_path,
             commit_id_path,
+            published_path,
+            lock_path,
+            objects_path,
+            config_path,
+            repos_path,
         }
     }
 }
@@ -50,3 +78,65 @@ pub fn get_absolute_path(rel_path: &str) -> PathBuf {
     path.push(rel_path);
     path
 }
+
+/// Whether `path` is the root, the home directory or one of its parents, which
+/// setman must never remove. Symlinks and `..` are resolved first.
+pub fn is_protected(path: &Path) -> bool {
+    let path = normalize(path);
+    let home = normalize(&home_dir().unwrap());
+    path.parent().is_none() || home.starts_with(&path)
+}
+
+// the canonical form of `path`, or `path` with `.` and `..` resolved if it does not exist
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(path) = path.canonicalize() {
+        return path;
+    }
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// A uniquely named path in the system's temporary directory. Whatever is created
+/// there is removed when it is dropped.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(prefix: &str) -> TempDir {
+        let dir_name = format!("{}-{}", prefix, Uuid::new_v4());
+        TempDir {
+            path: env::temp_dir().join(dir_name),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if self.path.exists() {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Expands a leading `~` to the home directory.
+pub fn expand_tilde(path: &str) -> PathBuf {
+    match path.strip_prefix('~') {
+        Some(rest) => get_absolute_path(rest.trim_start_matches('/')),
+        None => PathBuf::from(path),
+    }
+}
@@ -5,7 +5,8 @@
 use std::io::Error;
 
 use colored::*;
-use dialoguer::{Confirm, Input, Select};
+use console::{Key, Term};
+use dialoguer::{Confirm, Editor, Input, MultiSelect, Select};
 
 pub fn read(message: &str) -> Result<String, Error> {
     Input::<String>::new()
@@ -13,10 +14,110 @@ pub fn read(message: &str) -> Result<String, Error> {
         .interact_text()
 }
 
+// how many matches the fuzzy picker shows at once
+const MAX_SHOWN: usize = 10;
+
+/// Lets the user pick from `items`, filtering them as they type. Arrow keys move
+/// between the matches, enter picks one. When `multiple` is set, space marks
+/// matches and enter picks the marked ones. Returns the indices of the picked items.
+pub fn fuzzy_select(items: Vec<&str>, multiple: bool) -> Result<Vec<usize>, Error> {
+    let term = Term::stderr();
+    let mut query = String::new();
+    let mut cursor = 0;
+    let mut marked: Vec<usize> = vec![];
+    let mut drawn = 0;
+    let picked = loop {
+        let matches = fuzzy_matches(&items, &query);
+        let shown = matches.len().min(MAX_SHOWN);
+        cursor = cursor.min(shown.saturating_sub(1));
+
+        term.clear_last_lines(drawn)?;
+        term.write_line(&format!("{} Filter: {}", "[<]".bold().bright_cyan(), query))?;
+        for (row, i) in matches.iter().take(shown).enumerate() {
+            let pointer = if row == cursor { ">" } else { " " };
+            let mark = match (multiple, marked.contains(i)) {
+                (false, _) => "",
+                (true, true) => "[x] ",
+                (true, false) => "[ ] ",
+            };
+            term.write_line(&format!("{} {}{}", pointer, mark, items[*i]))?;
+        }
+        if matches.is_empty() {
+            term.write_line(&format!("  Nothing matches '{}'", query))?;
+        }
+        drawn = 1 + shown.max(1);
+
+        match term.read_key()? {
+            Key::Enter if multiple && !marked.is_empty() => break marked,
+            Key::Enter if !matches.is_empty() => break vec![matches[cursor]],
+            Key::Char(' ') if multiple && !matches.is_empty() => {
+                let i = matches[cursor];
+                match marked.iter().position(|m| *m == i) {
+                    Some(pos) => {
+                        marked.remove(pos);
+                    }
+                    None => marked.push(i),
+                }
+            }
+            Key::Char(c) if !c.is_control() => {
+                query.push(c);
+                cursor = 0;
+            }
+            Key::Backspace => {
+                query.pop();
+                cursor = 0;
+            }
+            Key::ArrowUp => cursor = cursor.saturating_sub(1),
+            Key::ArrowDown if cursor + 1 < shown => cursor += 1,
+            _ => {}
+        }
+    };
+    term.clear_last_lines(drawn)?;
+    Ok(picked)
+}
+
+// indices of the items matching `query`, best match first
+fn fuzzy_matches(items: &[&str], query: &str) -> Vec<usize> {
+    let mut matches: Vec<(usize, usize)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| fuzzy_score(query, item).map(|score| (score, i)))
+        .collect();
+    matches.sort();
+    matches.into_iter().map(|(_score, i)| i).collect()
+}
+
+// scores `item` by how spread out the characters of `query` are in it, lower is
+// better. None if the characters do not appear in order
+fn fuzzy_score(query: &str, item: &str) -> Option<usize> {
+    let item = item.to_lowercase();
+    let mut score = 0;
+    let mut last = None;
+    let mut chars = item.char_indices();
+    for wanted in query.to_lowercase().chars() {
+        let (pos, _c) = chars.find(|(_pos, c)| *c == wanted)?;
+        score += match last {
+            Some(last) => pos - last - 1,
+            None => pos,
+        };
+        last = Some(pos);
+    }
+    Some(score)
+}
+
 pub fn select(items: Vec<&str>) -> Result<usize, Error> {
     Select::new().items(&items).interact()
 }
 
+pub fn multi_select(items: Vec<&str>) -> Result<Vec<usize>, Error> {
+    MultiSelect::new().items(&items).interact()
+}
+
+/// Opens `text` in the user's editor, returns None if it was not saved.
+pub fn edit(text: &str) -> Result<Option<String>, Error> {
+    Editor::new().extension(".toml").edit(text)
+}
+
 pub fn are_you_sure(action: String) -> Result<bool, Error> {
     Confirm::new()
         .with_prompt(format!(
@@ -0,0 +1,104 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::hooks::{HookEvent, HookOutcome};
+use crate::largefiles::LargeFilePolicy;
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// What an action did for one application.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub app: String,
+    /// Files which were copied, or removed when uninstalling
+    pub files: Vec<String>,
+    /// Hooks which were run, in the order they ran
+    pub hooks: Vec<(HookEvent, HookOutcome)>,
+    /// Set if a failing pre hook made the action skip the application
+    pub skipped: bool,
+    pub warnings: Vec<Warning>,
+}
+
+/// Something worth telling the user about which did not stop an action.
+#[derive(Debug)]
+pub enum Warning {
+    /// A file is over its app's size limit
+    LargeFile {
+        file: String,
+        size: u64,
+        limit: u64,
+        policy: LargeFilePolicy,
+    },
+    /// A file likely holding secrets can be read by other users
+    Permissive { path: PathBuf, mode: u32 },
+    /// The metadata in a settings directory could not be parsed and was ignored
+    InvalidMeta { dir: PathBuf, message: String },
+    /// A recorded mode could not be parsed and was not applied
+    InvalidMode { file: String, mode: String },
+    /// A pointer refers to an object which is not in the side store
+    MissingObject {
+        file: String,
+        size: u64,
+        store: PathBuf,
+    },
+    /// A tracked file does not exist yet
+    MissingFile { file: String, dir: PathBuf },
+}
+
+impl Report {
+    pub fn new(app: &str) -> Report {
+        Report {
+            app: app.to_string(),
+            ..Report::default()
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::LargeFile {
+                file,
+                size,
+                limit,
+                policy,
+            } => {
+                write!(
+                    f,
+                    "{} is {} bytes, more than the limit of {} bytes",
+                    file, size, limit
+                )?;
+                match policy {
+                    LargeFilePolicy::Warn => Ok(()),
+                    LargeFilePolicy::Skip => write!(f, ", skipping it"),
+                    LargeFilePolicy::Store => {
+                        write!(f, ", it will be kept in the side store when pushing")
+                    }
+                }
+            }
+            Warning::Permissive { path, mode } => write!(
+                f,
+                "{:?} may contain secrets but has mode {:o}, consider running chmod 600 on it",
+                path, mode
+            ),
+            Warning::InvalidMeta { dir, message } => {
+                write!(f, "Ignoring invalid metadata in {:?}: {}", dir, message)
+            }
+            Warning::InvalidMode { file, mode } => {
+                write!(f, "Invalid mode '{}' recorded for {}", mode, file)
+            }
+            Warning::MissingObject { file, size, store } => write!(
+                f,
+                "{} ({} bytes) is not in the side store {:?}, skipping it",
+                file, size, store
+            ),
+            Warning::MissingFile { file, dir } => write!(
+                f,
+                "{} does not exist in {:?}, save it once it does",
+                file, dir
+            ),
+        }
+    }
+}
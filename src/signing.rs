@@ -0,0 +1,294 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::error::Error;
+use crate::paths;
+
+use git2::{Config, Oid, Repository, Signature};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use uuid::Uuid;
+
+/// Keys pulled commits have to be signed with, declared as an `[allowed_signers]`
+/// table in `config.toml`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AllowedSigners {
+    /// Fingerprints of OpenPGP and X.509 keys, the keys have to be in the gpg keyring
+    #[serde(default)]
+    pub fingerprints: Vec<String>,
+    /// SSH public keys, as in `~/.ssh/id_ed25519.pub`
+    #[serde(default)]
+    pub ssh_keys: Vec<String>,
+}
+
+/// How commits are signed, git's `gpg.format`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureFormat {
+    OpenPgp,
+    X509,
+    Ssh,
+}
+
+impl SignatureFormat {
+    // the format of an armored signature
+    fn of_signature(signature: &str) -> Option<SignatureFormat> {
+        if signature.starts_with("-----BEGIN PGP SIGNATURE-----") {
+            Some(SignatureFormat::OpenPgp)
+        } else if signature.starts_with("-----BEGIN SIGNED MESSAGE-----") {
+            Some(SignatureFormat::X509)
+        } else if signature.starts_with("-----BEGIN SSH SIGNATURE-----") {
+            Some(SignatureFormat::Ssh)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<SignatureFormat, Error> {
+        match config.get_string("gpg.format") {
+            Ok(format) => match format.as_str() {
+                "openpgp" => Ok(SignatureFormat::OpenPgp),
+                "x509" => Ok(SignatureFormat::X509),
+                "ssh" => Ok(SignatureFormat::Ssh),
+                _ => Err(Error::Signing(format!("unknown gpg.format '{}'", format))),
+            },
+            Err(_e) => Ok(SignatureFormat::OpenPgp),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SignatureFormat::OpenPgp => "openpgp",
+            SignatureFormat::X509 => "x509",
+            SignatureFormat::Ssh => "ssh",
+        }
+    }
+
+    // the program set in `gpg.<format>.program`, `gpg.program` also applies to openpgp
+    fn program(&self, config: &Config) -> String {
+        if let Ok(program) = config.get_string(&format!("gpg.{}.program", self.name())) {
+            return program;
+        }
+        match self {
+            SignatureFormat::OpenPgp => config
+                .get_string("gpg.program")
+                .unwrap_or_else(|_e| "gpg".to_string()),
+            SignatureFormat::X509 => "gpgsm".to_string(),
+            SignatureFormat::Ssh => "ssh-keygen".to_string(),
+        }
+    }
+}
+
+/// Signs `commit`, a buffer made by `Repository::commit_create_buffer`, if
+/// `commit.gpgsign` is set. Returns the armored signature to store in the commit's
+/// `gpgsig` header.
+pub fn sign_commit(
+    config: &Config,
+    commit: &str,
+    committer: &Signature,
+) -> Result<Option<String>, Error> {
+    if !config.get_bool("commit.gpgsign").unwrap_or(false) {
+        return Ok(None);
+    }
+    let format = SignatureFormat::from_config(config)?;
+    let signing_key = config.get_string("user.signingkey").ok();
+
+    // ssh keys given literally have to be handed to ssh-keygen as a file
+    let mut literal_key: Option<PathBuf> = None;
+    let args: Vec<String> = match format {
+        SignatureFormat::OpenPgp | SignatureFormat::X509 => {
+            // like git, fall back to the committer's identity
+            let key = signing_key.unwrap_or_else(|| {
+                format!(
+                    "{} <{}>",
+                    committer.name().unwrap_or_default(),
+                    committer.email().unwrap_or_default()
+                )
+            });
+            vec!["--status-fd=2".to_string(), "-bsau".to_string(), key]
+        }
+        SignatureFormat::Ssh => {
+            let key = match signing_key {
+                Some(key) => key,
+                None => {
+                    return Err(Error::Signing(
+                        "gpg.format is ssh but user.signingkey is not set".to_string(),
+                    ))
+                }
+            };
+            let key_path = match key.strip_prefix("key::") {
+                Some(literal) => {
+                    let path = temp_file("key", &format!("{}\n", literal))?;
+                    literal_key = Some(path.clone());
+                    path
+                }
+                None => paths::expand_tilde(&key),
+            };
+            vec![
+                "-Y".to_string(),
+                "sign".to_string(),
+                "-n".to_string(),
+                "git".to_string(),
+                "-f".to_string(),
+                key_path.display().to_string(),
+            ]
+        }
+    };
+
+    let program = format.program(config);
+    let result = run(&program, &args, commit);
+    if let Some(path) = literal_key {
+        let _ = fs::remove_file(path);
+    }
+    let output = result.map_err(|e| Error::Signing(e.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::Signing(format!(
+            "{} failed: {}",
+            program,
+            stderr.trim()
+        )));
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).to_string()))
+}
+
+/// Makes sure the commit `id` in `repo` is signed with one of the allowed keys.
+/// The programs set in git's config are used to check the signature.
+pub fn verify_commit(repo: &Repository, id: Oid, signers: &AllowedSigners) -> Result<(), Error> {
+    let unverified = |reason: String| Err(Error::Unverified(id.to_string(), reason));
+    let (signature, content) = match repo.extract_signature(&id, None) {
+        Ok(signed) => signed,
+        Err(_e) => return unverified("it is not signed".to_string()),
+    };
+    let signature = signature.as_str().unwrap_or_default();
+    let format = match SignatureFormat::of_signature(signature) {
+        Some(format) => format,
+        None => return unverified("the signature has an unknown format".to_string()),
+    };
+    let signature_path = temp_file("sig", signature)?;
+    let result = match format {
+        SignatureFormat::OpenPgp | SignatureFormat::X509 => {
+            verify_fingerprint(&format, &repo.config()?, &signature_path, &content, signers)
+        }
+        SignatureFormat::Ssh => verify_ssh_key(&repo.config()?, &signature_path, &content, signers),
+    };
+    let _ = fs::remove_file(signature_path);
+    match result? {
+        None => Ok(()),
+        Some(reason) => unverified(reason),
+    }
+}
+
+// checks a gpg or gpgsm signature, returns why it is not allowed if it isn't
+fn verify_fingerprint(
+    format: &SignatureFormat,
+    config: &Config,
+    signature_path: &Path,
+    content: &[u8],
+    signers: &AllowedSigners,
+) -> Result<Option<String>, Error> {
+    let program = format.program(config);
+    let args = vec![
+        "--status-fd=1".to_string(),
+        "--verify".to_string(),
+        signature_path.display().to_string(),
+        "-".to_string(),
+    ];
+    let output = match run(&program, &args, content) {
+        Ok(output) => output,
+        Err(e) => return Ok(Some(e.to_string())),
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Ok(Some(stderr.trim().to_string()));
+    }
+    // [GNUPG:] VALIDSIG <fingerprint> ... <fingerprint of the primary key>
+    let status = String::from_utf8_lossy(&output.stdout);
+    let fingerprints: Vec<&str> = match status
+        .lines()
+        .find_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+    {
+        Some(fields) => fields.split_whitespace().collect(),
+        None => return Ok(Some("the signature is not valid".to_string())),
+    };
+    let fingerprints = [fingerprints[0], fingerprints[fingerprints.len() - 1]];
+    let allowed = signers.fingerprints.iter().any(|allowed| {
+        let allowed = allowed.replace(' ', "");
+        fingerprints
+            .iter()
+            .any(|fingerprint| fingerprint.eq_ignore_ascii_case(&allowed))
+    });
+    if allowed {
+        Ok(None)
+    } else {
+        Ok(Some(format!("key {} is not allowed", fingerprints[0])))
+    }
+}
+
+// checks an ssh signature, returns why it is not allowed if it isn't
+fn verify_ssh_key(
+    config: &Config,
+    signature_path: &Path,
+    content: &[u8],
+    signers: &AllowedSigners,
+) -> Result<Option<String>, Error> {
+    // ssh-keygen looks keys up by principal, all allowed keys share one
+    let allowed: String = signers
+        .ssh_keys
+        .iter()
+        .map(|key| format!("setman namespaces=\"git\" {}\n", key.trim()))
+        .collect();
+    let allowed_path = temp_file("allowed-signers", &allowed)?;
+    let args = vec![
+        "-Y".to_string(),
+        "verify".to_string(),
+        "-f".to_string(),
+        allowed_path.display().to_string(),
+        "-I".to_string(),
+        "setman".to_string(),
+        "-n".to_string(),
+        "git".to_string(),
+        "-s".to_string(),
+        signature_path.display().to_string(),
+    ];
+    let result = run(&SignatureFormat::Ssh.program(config), &args, content);
+    let _ = fs::remove_file(allowed_path);
+    match result {
+        Ok(output) if output.status.success() => Ok(None),
+        Ok(output) => Ok(Some(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+        Err(e) => Ok(Some(e.to_string())),
+    }
+}
+
+// writes `content` to a new file in the temporary directory, the caller removes it
+fn temp_file(kind: &str, content: &str) -> Result<PathBuf, Error> {
+    let path = env::temp_dir().join(format!("setman-{}-{}", kind, Uuid::new_v4()));
+    fs::write(&path, content)?;
+    Ok(path)
+}
+
+// runs a signing program with `input` on stdin
+fn run(program: &str, args: &[String], input: impl AsRef<[u8]>) -> io::Result<Output> {
+    let mut child = match Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            let message = format!("could not run {}: {}", program, e);
+            return Err(io::Error::new(e.kind(), message));
+        }
+    };
+    child.stdin.take().unwrap().write_all(input.as_ref())?;
+    child.wait_with_output()
+}
@@ -0,0 +1,305 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::error::Error;
+use crate::fileman;
+use crate::hooks;
+use crate::largefiles;
+use crate::meta;
+use crate::paths;
+use crate::report::{Report, Warning};
+
+use fileman::App;
+use git2::Patch;
+use hooks::{HookEvent, HookOutcome};
+use meta::{AppMeta, CreatedDirs};
+use paths::Paths;
+use std::fs;
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+
+/// The local copies of the applications' files, one directory per application.
+#[derive(Debug, Clone)]
+pub struct Store {
+    path: PathBuf,
+}
+
+/// How one of an app's installed files differs from the saved one.
+#[derive(Debug)]
+pub enum FileDiff {
+    /// The file is saved but not installed
+    OnlySaved(String),
+    /// The file is installed but not saved
+    OnlyInstalled(String),
+    /// The file is over the app's size limit and differs
+    Large(String),
+    /// The file is binary and differs
+    Binary(String),
+    /// Unified diff from the saved to the installed file
+    Text(String, String),
+}
+
+impl Default for Store {
+    fn default() -> Store {
+        Store::at(Paths::default().settings_path)
+    }
+}
+
+impl Store {
+    pub fn at(path: PathBuf) -> Store {
+        Store { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Directory holding the saved files of the app named `app_name`.
+    pub fn app_path(&self, app_name: &str) -> PathBuf {
+        self.path.join(app_name)
+    }
+
+    /// Names of the applications which have a directory in the store.
+    pub fn app_names(&self) -> IOResult<Vec<String>> {
+        fileman::get_dir_names_in_path(&self.path)
+    }
+
+    /// Copies the app's saved files to its config path and applies their recorded modes.
+    pub fn install(&self, app: &App) -> Result<Report, Error> {
+        let mut report = Report::new(&app.name);
+        let local_path = self.app_path(&app.name);
+        let changed = fileman::changed_files(&app.file_names, &local_path, &app.config_path);
+        if !run_hook(app, HookEvent::PreInstall, &changed, &mut report)? {
+            return Ok(report);
+        }
+
+        let created_dirs = fileman::missing_dirs(&app.config_path);
+        fileman::copy_files(&app.file_names, &local_path, &app.config_path)?;
+        if !created_dirs.is_empty() {
+            let mut created = CreatedDirs::load(&self.path)?;
+            created.record(&app.name, created_dirs);
+            created.write(&self.path)?;
+        }
+        let app_meta = AppMeta::load_or_default(&local_path, &mut report.warnings);
+        for file in app.file_names.iter() {
+            let path = app.config_path.join(file);
+            report.warnings.extend(app_meta.apply(file, &path)?);
+            report.warnings.extend(meta::check_permissions(&path));
+        }
+        report.files = app.file_names.clone();
+
+        run_hook(app, HookEvent::PostInstall, &changed, &mut report)?;
+        Ok(report)
+    }
+
+    /// The files `uninstall` would remove from the app's config path.
+    pub fn uninstall_preview(&self, app: &App) -> Result<Vec<PathBuf>, Error> {
+        fileman::installed_files(&app.file_names, &app.config_path)
+    }
+
+    /// Removes the app's tracked files from its config path, and the directories
+    /// installing it created if nothing else is left in them.
+    pub fn uninstall(&self, app: &App) -> Result<Report, Error> {
+        let mut report = Report::new(&app.name);
+        let files = fileman::installed_files(&app.file_names, &app.config_path)?;
+        if !run_hook(app, HookEvent::PreUninstall, &app.file_names, &mut report)? {
+            return Ok(report);
+        }
+        let mut created = CreatedDirs::load(&self.path)?;
+        let created_dirs = created.apps.remove(&app.name).unwrap_or_default();
+        let removed = fileman::remove_installed_files(&files, &created_dirs)?;
+        // directories still holding other files are removed by a later uninstall
+        let kept: Vec<PathBuf> = created_dirs
+            .into_iter()
+            .filter(|dir| dir.exists() && !removed.contains(dir))
+            .collect();
+        if !kept.is_empty() {
+            created.record(&app.name, kept);
+        }
+        created.write(&self.path)?;
+        report.files = removed
+            .iter()
+            .map(|path| match path.strip_prefix(&app.config_path) {
+                Ok(rel_path) if rel_path != Path::new("") => rel_path.display().to_string(),
+                _ => path.display().to_string(),
+            })
+            .collect();
+        run_hook(app, HookEvent::PostUninstall, &app.file_names, &mut report)?;
+        Ok(report)
+    }
+
+    /// Copies the app's files from its config path to the store.
+    pub fn save(&self, app: &App) -> Result<Report, Error> {
+        let mut report = Report::new(&app.name);
+        let changed =
+            fileman::changed_files(&app.file_names, &app.config_path, &self.app_path(&app.name));
+        if !run_hook(app, HookEvent::PreSave, &changed, &mut report)? {
+            return Ok(report);
+        }
+        self.copy_to_store(app, &app.file_names, &mut report)?;
+        run_hook(app, HookEvent::PostSave, &changed, &mut report)?;
+        Ok(report)
+    }
+
+    /// Copies some of the app's files to the store without running any hooks.
+    pub fn save_files(&self, app: &App, file_names: &[String]) -> Result<Report, Error> {
+        let mut report = Report::new(&app.name);
+        self.copy_to_store(app, file_names, &mut report)?;
+        Ok(report)
+    }
+
+    /// Removes the saved files of the app named `app_name` along with its directory,
+    /// so the next push removes the app from the backend. Returns the removed paths.
+    pub fn remove(&self, app_name: &str) -> IOResult<Vec<PathBuf>> {
+        let app_path = self.app_path(app_name);
+        if !app_path.exists() {
+            return Ok(Vec::new());
+        }
+        let removed = fileman::find_files(&app_path)?
+            .into_iter()
+            .map(|file| app_path.join(file))
+            .collect();
+        fs::remove_dir_all(&app_path)?;
+        Ok(removed)
+    }
+
+    /// Brings the store up to date with `app` replacing `original`: moves the app's
+    /// directory if it was renamed, drops files which are no longer tracked and saves
+    /// newly tracked ones.
+    pub fn update_app(&self, original: &App, app: &App) -> Result<Report, Error> {
+        let mut report = Report::new(&app.name);
+        let old_local_path = self.app_path(&original.name);
+        let local_path = self.app_path(&app.name);
+        if original.name != app.name && old_local_path.exists() {
+            fs::rename(&old_local_path, &local_path)?;
+        }
+
+        for file_name in original.file_names.iter() {
+            let stored_file = local_path.join(file_name);
+            if !app.file_names.contains(file_name) && stored_file.exists() {
+                fs::remove_file(&stored_file)?;
+            }
+        }
+
+        let mut added: Vec<String> = Vec::new();
+        for file in app.file_names.iter() {
+            if original.file_names.contains(file) {
+                continue;
+            }
+            match app.config_path.join(file).exists() {
+                true => added.push(file.clone()),
+                false => report.warnings.push(Warning::MissingFile {
+                    file: file.clone(),
+                    dir: app.config_path.clone(),
+                }),
+            }
+        }
+        if !added.is_empty() {
+            self.copy_to_store(app, &added, &mut report)?;
+        } else if local_path.exists() {
+            let mut app_meta = AppMeta::load_or_default(&local_path, &mut report.warnings);
+            app_meta.retain_files(&app.file_names);
+            app_meta.write(&local_path)?;
+        }
+        Ok(report)
+    }
+
+    /// Compares the app's installed files to the saved ones, only differing files are returned.
+    pub fn diff(&self, app: &App) -> Result<Vec<FileDiff>, Error> {
+        let local_path = self.app_path(&app.name);
+        let mut diffs: Vec<FileDiff> = Vec::new();
+        for file in app.file_names.iter() {
+            let saved_path = local_path.join(file);
+            let installed_path = app.config_path.join(file);
+            match (saved_path.exists(), installed_path.exists()) {
+                (true, true) => {}
+                (true, false) => {
+                    diffs.push(FileDiff::OnlySaved(file.clone()));
+                    continue;
+                }
+                (false, true) => {
+                    diffs.push(FileDiff::OnlyInstalled(file.clone()));
+                    continue;
+                }
+                (false, false) => continue,
+            }
+            if largefiles::is_large(app, &saved_path) || largefiles::is_large(app, &installed_path)
+            {
+                if !fileman::changed_files(
+                    std::slice::from_ref(file),
+                    &local_path,
+                    &app.config_path,
+                )
+                .is_empty()
+                {
+                    diffs.push(FileDiff::Large(file.clone()));
+                }
+                continue;
+            }
+
+            let saved = fs::read(&saved_path)?;
+            let installed = fs::read(&installed_path)?;
+            if saved == installed {
+                continue;
+            }
+            if largefiles::is_binary(&saved) || largefiles::is_binary(&installed) {
+                diffs.push(FileDiff::Binary(file.clone()));
+                continue;
+            }
+            let mut patch = Patch::from_buffers(
+                &saved,
+                Some(Path::new(file)),
+                &installed,
+                Some(Path::new(file)),
+                None,
+            )?;
+            let buf = patch.to_buf()?;
+            let text = buf.as_str().unwrap_or_default().to_string();
+            diffs.push(FileDiff::Text(file.clone(), text));
+        }
+        Ok(diffs)
+    }
+
+    // saves `file_names` of the app, leaving out large files the app's policy skips
+    fn copy_to_store(
+        &self,
+        app: &App,
+        file_names: &[String],
+        report: &mut Report,
+    ) -> Result<(), Error> {
+        let (file_names, warnings) = largefiles::filter_large_files(app, file_names);
+        report.warnings.extend(warnings);
+        let local_path = self.app_path(&app.name);
+        fileman::copy_files(&file_names, &app.config_path, &local_path)?;
+
+        // record modes (and mtimes) of the saved files
+        let mut app_meta = AppMeta::load_or_default(&local_path, &mut report.warnings);
+        for file in file_names.iter() {
+            let path = app.config_path.join(file);
+            app_meta.record(file, &path, app.preserve_mtimes)?;
+            report.warnings.extend(meta::check_permissions(&path));
+        }
+        app_meta.retain_files(&app.file_names);
+        app_meta.write(&local_path)?;
+        report.files.extend(file_names);
+        Ok(())
+    }
+}
+
+// runs the app's hook for `event` and records it in `report`.
+// returns false if the action should be skipped for the app
+pub(crate) fn run_hook(
+    app: &App,
+    event: HookEvent,
+    changed_files: &[String],
+    report: &mut Report,
+) -> Result<bool, Error> {
+    let outcome = match hooks::run_hook(app, event, changed_files)? {
+        Some(outcome) => outcome,
+        None => return Ok(true),
+    };
+    report.skipped = matches!(outcome, HookOutcome::Skipped(_));
+    report.hooks.push((event, outcome));
+    Ok(!report.skipped)
+}
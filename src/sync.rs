@@ -0,0 +1,1335 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+use crate::backend;
+use crate::config;
+use crate::error::Error;
+use crate::fileman;
+use crate::hooks;
+use crate::largefiles;
+use crate::meta;
+use crate::paths;
+use crate::report::{Report, Warning};
+use crate::signing::AllowedSigners;
+use crate::store;
+
+use backend::{Revision, SyncBackend};
+use config::Config;
+use fileman::{App, Apps, TOMLError};
+use git2::{ObjectType, Oid};
+use hooks::HookEvent;
+use largefiles::LargeFilePolicy;
+use paths::{Paths, TempDir};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+use store::Store;
+
+/// Pushes the store to a sync backend and pulls it back from there.
+pub struct SyncEngine {
+    backend: Box<dyn SyncBackend>,
+    store: Store,
+    // holds the fetched settings, removed when the engine is dropped
+    work_dir: TempDir,
+    // holds the revision the local settings are based on
+    revision_path: PathBuf,
+    // holds the files of that revision, see `PublishedFiles`
+    published_path: PathBuf,
+    allowed_signers: Option<AllowedSigners>,
+}
+
+#[derive(Debug)]
+pub struct PushReport {
+    /// The published revision, a commit id for git
+    pub revision: String,
+    /// Large files which were moved to the side store, they are pushed as objects apart from the apps
+    pub stored: Vec<StoredFile>,
+    /// Why the revision could not be delivered, it is kept locally until the next push
+    pub deferred: Option<Error>,
+    /// Revisions still waiting to be delivered
+    pub pending: Vec<Revision>,
+    /// Revisions published elsewhere which the pushed one was rebased onto
+    pub integrated: Vec<Revision>,
+}
+
+#[derive(Debug)]
+pub struct StoredFile {
+    pub app: String,
+    pub file: String,
+    pub oid: git2::Oid,
+}
+
+/// What `SyncEngine::sync` did.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Apps pulled from the backend, empty if it had nothing new
+    pub pulled: Vec<Report>,
+    /// Apps whose local changes were saved
+    pub saved: Vec<Report>,
+    /// Apps installed to bring in pulled changes
+    pub installed: Vec<Report>,
+    /// Apps changed both locally and in the backend, they were neither saved nor installed
+    pub conflicts: Vec<Conflict>,
+    /// None if there was nothing to push
+    pub pushed: Option<PushReport>,
+}
+
+#[derive(Debug)]
+pub struct Conflict {
+    pub app: String,
+    pub files: Vec<String>,
+}
+
+impl SyncEngine {
+    /// Syncs the default store using the default profile.
+    pub fn new() -> Result<SyncEngine, Error> {
+        SyncEngine::for_profile(None)
+    }
+
+    /// Syncs the default store using the profile named `profile`, see `Config::profile`.
+    pub fn for_profile(profile: Option<&str>) -> Result<SyncEngine, Error> {
+        let config = Config::load()?;
+        let name = profile.or(config.default_profile.as_deref());
+        let backend = config
+            .profile(name)?
+            .backend(name, config.allowed_signers.as_ref());
+        let mut engine = SyncEngine::with(backend, Store::default());
+        // each profile keeps track of its own revision
+        if let Some(name) = name {
+            let file_name = format!("latest_commit.{}", name);
+            engine.revision_path = engine.revision_path.with_file_name(file_name);
+            let file_name = format!("published_files.{}", name);
+            engine.published_path = engine.published_path.with_file_name(file_name);
+        }
+        engine.allowed_signers = config.allowed_signers;
+        Ok(engine)
+    }
+
+    pub fn with(backend: Box<dyn SyncBackend>, store: Store) -> SyncEngine {
+        SyncEngine {
+            backend,
+            store,
+            work_dir: TempDir::new("setman-tmp"),
+            revision_path: Paths::default().commit_id_path,
+            published_path: Paths::default().published_path,
+            allowed_signers: None,
+        }
+    }
+
+    pub fn backend(&self) -> &dyn SyncBackend {
+        self.backend.as_ref()
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Makes sure the backend's target is reachable.
+    pub fn check_remote(&self) -> Result<(), Error> {
+        self.backend.check()
+    }
+
+    /// Publishes the store with `commit_msg` describing the change. Backends keeping
+    /// a local copy publish to it even if the target can't be reached, the revision
+    /// is then delivered by a later push.
+    pub fn push(&self, apps: &Apps, commit_msg: &str) -> Result<PushReport, Error> {
+        let (_revision, deferred) = self.fetch_or_checkout()?;
+        self.push_fetched(apps, commit_msg, deferred, &[])
+    }
+
+    // publishes the store on top of the fetched settings, the apps in `unchanged`
+    // are published as they were fetched
+    fn push_fetched(
+        &self,
+        apps: &Apps,
+        commit_msg: &str,
+        mut deferred: Option<Error>,
+        unchanged: &[&String],
+    ) -> Result<PushReport, Error> {
+        // the working copy is rebuilt from the store, so apps which were removed
+        // from it are removed from the backend as well
+        for entry in fs::read_dir(self.work_dir.path())? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if [
+                ".git",
+                backend::REVISION_FILE_NAME,
+                largefiles::OBJECTS_DIR_NAME,
+            ]
+            .contains(&&*name)
+                || unchanged.contains(&&name)
+            {
+                continue;
+            }
+            match entry.file_type()?.is_dir() {
+                true => fs::remove_dir_all(entry.path())?,
+                false => fs::remove_file(entry.path())?,
+            }
+        }
+        let object_store = largefiles::object_store(apps);
+        let published_objects = self.work_dir.path().join(largefiles::OBJECTS_DIR_NAME);
+        let mut stored: Vec<StoredFile> = Vec::new();
+        for dir_name in self.store.app_names()? {
+            let source = self.store.app_path(&dir_name);
+            let dest = self.work_dir.path().join(&dir_name);
+            let app = match apps.items.iter().find(|app| app.name == dir_name) {
+                Some(app) => app,
+                // pulled directories need no app entry, they are published as they are
+                None => {
+                    if !unchanged.contains(&&dir_name) {
+                        backend::copy_tree(&source, &dest)?;
+                    }
+                    continue;
+                }
+            };
+            // pulling resolved the pointers of unchanged apps as well
+            if !unchanged.contains(&&dir_name) {
+                let mut file_names = app.file_names.clone();
+                file_names.push(meta::META_FILE_NAME.to_string());
+                // skipped large files and files which don't exist yet were never saved
+                file_names.retain(|file| source.join(file).exists());
+                fileman::copy_files(&file_names, &source, &dest)?;
+            }
+
+            if app.large_files != LargeFilePolicy::Store {
+                continue;
+            }
+            for file in app.file_names.iter() {
+                let path = dest.join(file);
+                if largefiles::is_large(app, &path) {
+                    let pointer = largefiles::replace_with_pointer(&object_store, &path)?;
+                    largefiles::copy_object(&object_store, &published_objects, pointer.oid)?;
+                    stored.push(StoredFile {
+                        app: app.name.clone(),
+                        file: file.clone(),
+                        oid: pointer.oid,
+                    });
+                }
+            }
+        }
+        let published = PublishedFiles::of_dir(self.work_dir.path())?;
+        // objects no pointer refers to anymore are dropped
+        if published_objects.exists() {
+            let referenced: BTreeSet<&String> = published
+                .apps
+                .values()
+                .flat_map(|files| files.values())
+                .collect();
+            for entry in fs::read_dir(&published_objects)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !referenced.contains(&name) {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+        let mut revision = self.backend.publish(self.work_dir.path(), commit_msg)?;
+        // the store does not hold integrated revisions, a later pull brings them in
+        fileman::write_atomic(&self.revision_path, &revision)?;
+        published.write(&self.published_path)?;
+        let mut integrated: Vec<Revision> = Vec::new();
+        if deferred.is_none() {
+            match self.backend.deliver() {
+                Ok(delivery) => {
+                    revision = delivery.revision.unwrap_or(revision);
+                    integrated = delivery.integrated;
+                    deferred = delivery.deferred;
+                }
+                // the revision stays pending until the target can be reached
+                Err(e) if e.is_network() => deferred = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(PushReport {
+            revision,
+            stored,
+            deferred,
+            pending: self.backend.pending()?,
+            integrated,
+        })
+    }
+
+    /// Revisions which were published while the target was unreachable.
+    pub fn pending(&self) -> Result<Vec<Revision>, Error> {
+        self.backend.pending()
+    }
+
+    /// Copies the published settings into the store, running the pull hooks of the apps.
+    /// Directories in the backend are not required to have an app entry locally.
+    pub fn pull(&self, apps: &Apps) -> Result<Vec<Report>, Error> {
+        let revision = self.fetch()?;
+        self.pull_fetched(apps, revision, &Changes::default())
+    }
+
+    /// Pulls, then saves and installs `selected` and pushes what was saved, all in
+    /// one go. Saves which were not pushed yet are kept and pushed as well. Apps changed
+    /// both locally and in the backend are reported as conflicts and left alone. If a
+    /// step fails, the store, the installed files of `selected` and the local revision
+    /// are put back the way they were. A push which can't reach the target is not a
+    /// failure: the revision stays pending in the backend's local copy until a later
+    /// push delivers it, see `PushReport::deferred`.
+    pub fn sync(
+        &self,
+        apps: &Apps,
+        selected: &[App],
+        commit_msg: &str,
+    ) -> Result<SyncReport, Error> {
+        let mut backup_path = self.work_dir.path().to_path_buf();
+        backup_path.set_extension("backup");
+        let records = [self.revision_path.as_path(), self.published_path.as_path()];
+        let snapshot = Snapshot::take(backup_path, &self.store, selected, &records)?;
+        match self.sync_with(apps, selected, commit_msg, &snapshot) {
+            Ok(report) => Ok(report),
+            Err(e) => match snapshot.restore() {
+                Ok(()) => Err(e),
+                Err(restore_error) => {
+                    Err(Error::RestoreFailed(Box::new(e), Box::new(restore_error)))
+                }
+            },
+        }
+    }
+
+    fn sync_with(
+        &self,
+        apps: &Apps,
+        selected: &[App],
+        commit_msg: &str,
+        snapshot: &Snapshot,
+    ) -> Result<SyncReport, Error> {
+        let mut report = SyncReport::default();
+        let (revision, deferred) = self.fetch_or_checkout()?;
+        let local_revision = fs::read_to_string(&self.revision_path).ok();
+        let behind =
+            revision.is_some() && local_revision.as_ref().map(|r| r.trim().to_string()) != revision;
+        let fetched = PublishedFiles::of_dir(self.work_dir.path())?;
+        // what the store is based on, unknown if nothing was synced with this profile
+        let base = match (behind, local_revision) {
+            (false, _) => Some(fetched.clone()),
+            (true, Some(_)) => PublishedFiles::load(&self.published_path)?,
+            (true, None) => None,
+        };
+        let stored = PublishedFiles::of_dir(self.store.path())?;
+        let changes = Changes::between(base.as_ref(), &stored, &fetched);
+        for (app, files) in changes.conflicts.iter() {
+            report.conflicts.push(Conflict {
+                app: app.clone(),
+                files: files.clone(),
+            });
+        }
+        if behind {
+            report.pulled = self.pull_fetched(apps, revision, &changes)?;
+        }
+
+        for app in selected {
+            if changes.conflicts.contains_key(&app.name) {
+                continue;
+            }
+            // the store as it was before pulling is what both sides changed
+            let base = snapshot.store_backup_path.join(&app.name);
+            let local_path = self.store.app_path(&app.name);
+            let local: Vec<String> =
+                fileman::changed_files(&app.file_names, &app.config_path, &base)
+                    .into_iter()
+                    .filter(|file| app.config_path.join(file).exists())
+                    .collect();
+            let remote = fileman::changed_files(&app.file_names, &local_path, &base);
+            let conflicting: Vec<String> = local
+                .iter()
+                .filter(|file| remote.contains(file))
+                .filter(|file| {
+                    // both sides making the same change is fine
+                    !fileman::changed_files(&[file.to_string()], &app.config_path, &local_path)
+                        .is_empty()
+                })
+                .cloned()
+                .collect();
+            if !conflicting.is_empty() {
+                report.conflicts.push(Conflict {
+                    app: app.name.clone(),
+                    files: conflicting,
+                });
+                continue;
+            }
+            if !local.is_empty() {
+                report.saved.push(self.store.save_files(app, &local)?);
+            }
+            if !remote.is_empty() {
+                report.installed.push(self.store.install(app)?);
+            }
+        }
+
+        if !report.saved.is_empty()
+            || !changes.local.is_empty()
+            || !self.backend.pending()?.is_empty()
+        {
+            let unchanged: Vec<&String> = changes.conflicts.keys().collect();
+            report.pushed = Some(self.push_fetched(apps, commit_msg, deferred, &unchanged)?);
+        }
+        Ok(report)
+    }
+
+    // copies the fetched settings into the store once they are verified, except for the
+    // files in `changes`
+    fn pull_fetched(
+        &self,
+        apps: &Apps,
+        revision: Option<String>,
+        changes: &Changes,
+    ) -> Result<Vec<Report>, Error> {
+        if let Some(revision) = revision {
+            self.verify(&revision)?;
+            fileman::write_atomic(&self.revision_path, revision)?;
+            PublishedFiles::of_dir(self.work_dir.path())?.write(&self.published_path)?;
+        }
+        let mut reports: Vec<Report> = Vec::new();
+        let dir_names = fileman::get_dir_names_in_path(self.work_dir.path())?;
+        let published_objects = self.work_dir.path().join(largefiles::OBJECTS_DIR_NAME);
+        for dir_name in dir_names
+            .into_iter()
+            .filter(|name| name != ".git" && name != largefiles::OBJECTS_DIR_NAME)
+        {
+            let source = self.work_dir.path().join(&dir_name);
+            let dest = self.store.app_path(&dir_name);
+            let mut report = Report::new(&dir_name);
+            let file_names: Vec<String> = fs::read_dir(&source)?
+                .map(|entry| Ok(entry?.file_name().to_str().unwrap().to_string()))
+                .filter(|file| match file {
+                    Ok(file) => !changes.contains(&dir_name, file),
+                    Err(_e) => true,
+                })
+                .collect::<Result<_, Error>>()?;
+            let file_names = resolve_pointers(
+                apps,
+                &source,
+                &published_objects,
+                file_names,
+                &mut report.warnings,
+            )?;
+
+            let app = apps.items.iter().find(|app| app.name == dir_name);
+            let changed = fileman::changed_files(&file_names, &source, &dest);
+            if let Some(app) = app {
+                if !store::run_hook(app, HookEvent::PrePull, &changed, &mut report)? {
+                    reports.push(report);
+                    continue;
+                }
+            }
+            fileman::copy_files(&file_names, &source, &dest)?;
+            report.files = file_names;
+            if let Some(app) = app {
+                store::run_hook(app, HookEvent::PostPull, &changed, &mut report)?;
+            }
+            reports.push(report);
+        }
+        Ok(reports)
+    }
+
+    /// Finds the revision `at` refers to, for backends which keep a history.
+    /// See `GitRepo::find_commit` for what git accepts.
+    pub fn find_revision(&self, at: &str) -> Result<Revision, Error> {
+        if !self.work_dir.path().exists() {
+            self.fetch()?;
+        }
+        self.backend.find_revision(self.work_dir.path(), at)
+    }
+
+    /// Replaces the app's saved files with the ones in `revision`. Installed files are not
+    /// touched. With allowed signers configured, `revision` has to be signed by one of them.
+    pub fn restore(&self, apps: &Apps, app: &App, revision: &str) -> Result<Report, Error> {
+        if !self.work_dir.path().exists() {
+            self.fetch()?;
+        }
+        if let Some(signers) = &self.allowed_signers {
+            self.backend.verify(revision, None, signers)?;
+        }
+        let mut report = Report::new(&app.name);
+
+        // restore to a temporary directory first so large files can be resolved before
+        // touching the store
+        let restore_dir = TempDir::new("setman-restore");
+        let restore_path = restore_dir.path().join(&app.name);
+        let restored =
+            self.backend
+                .restore_dir(self.work_dir.path(), revision, &app.name, &restore_path)?;
+        // the objects large files point to are published along with the revision
+        let objects_path = restore_dir.path().join(largefiles::OBJECTS_DIR_NAME);
+        let has_pointers = restored
+            .iter()
+            .any(|file| largefiles::read_pointer(&restore_path.join(file)).is_some());
+        if has_pointers {
+            // revisions from before objects were published have none, their large
+            // files are reported as missing
+            let _ = self.backend.restore_dir(
+                self.work_dir.path(),
+                revision,
+                largefiles::OBJECTS_DIR_NAME,
+                &objects_path,
+            );
+        }
+        let restored = resolve_pointers(
+            apps,
+            &restore_path,
+            &objects_path,
+            restored,
+            &mut report.warnings,
+        )?;
+        fileman::copy_files(&restored, &restore_path, &self.store.app_path(&app.name))?;
+        report.files = restored;
+        Ok(report)
+    }
+
+    /// Returns whether the local settings are based on the latest published revision.
+    pub fn is_up_to_date(&self) -> Result<bool, Error> {
+        let revision = match self.fetch()? {
+            Some(revision) => revision,
+            None => return Ok(true),
+        };
+        // nothing has been pulled or pushed with this profile yet
+        let local_revision = fs::read_to_string(&self.revision_path).unwrap_or_default();
+        Ok(local_revision.trim() == revision)
+    }
+
+    // checks the signatures of the revisions published since the last sync
+    // if allowed signers are configured
+    fn verify(&self, revision: &str) -> Result<(), Error> {
+        let signers = match &self.allowed_signers {
+            Some(signers) => signers,
+            None => return Ok(()),
+        };
+        let local_revision = fs::read_to_string(&self.revision_path).ok();
+        let since = local_revision.as_deref().map(str::trim);
+        if since == Some(revision) {
+            return Ok(());
+        }
+        self.backend.verify(revision, since, signers)
+    }
+
+    // fetches like `fetch`, falling back to the backend's local copy if the target
+    // can't be reached. The network error is returned along with the revision in that
+    // case, any other error (a conflict, rejected credentials) is returned as is
+    fn fetch_or_checkout(&self) -> Result<(Option<String>, Option<Error>), Error> {
+        let error = match self.fetch() {
+            Ok(revision) => return Ok((revision, None)),
+            Err(e) if e.is_network() => e,
+            Err(e) => return Err(e),
+        };
+        if self.work_dir.path().exists() {
+            fs::remove_dir_all(self.work_dir.path())?;
+        }
+        match self.backend.checkout(self.work_dir.path())? {
+            true => Ok((None, Some(error))),
+            false => Err(error),
+        }
+    }
+
+    // fetches the published settings into a fresh working copy
+    fn fetch(&self) -> Result<Option<String>, Error> {
+        if self.work_dir.path().exists() {
+            fs::remove_dir_all(self.work_dir.path())?;
+        }
+        self.backend.fetch(self.work_dir.path())
+    }
+}
+
+// replaces pointer files in `dir` with their content from the side store, objects
+// it doesn't have yet are taken from `published_objects`.
+// returns the files that can be copied, pointers to missing objects are left out
+fn resolve_pointers(
+    apps: &Apps,
+    dir: &Path,
+    published_objects: &Path,
+    file_names: Vec<String>,
+    warnings: &mut Vec<Warning>,
+) -> Result<Vec<String>, Error> {
+    let object_store = largefiles::object_store(apps);
+    let mut resolved: Vec<String> = Vec::new();
+    for file in file_names {
+        let path = dir.join(&file);
+        if let Some(pointer) = largefiles::read_pointer(&path) {
+            largefiles::copy_object(published_objects, &object_store, pointer.oid)?;
+            if !largefiles::fetch_object(&object_store, &pointer, &path)? {
+                warnings.push(Warning::MissingObject {
+                    file,
+                    size: pointer.size,
+                    store: object_store.clone(),
+                });
+                continue;
+            }
+        }
+        resolved.push(file);
+    }
+    Ok(resolved)
+}
+
+// the content ids of the files in each app directory of a published revision, pointers
+// count as the file they point to. Recorded when pushing and pulling, so the next sync
+// can tell saves which were not pushed yet from changes published elsewhere
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct PublishedFiles {
+    #[serde(default)]
+    apps: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl PublishedFiles {
+    // reads the record at `path`, None if there is none
+    fn load(path: &Path) -> Result<Option<PublishedFiles>, Error> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(toml::from_str(&content).map_err(TOMLError::from)?)),
+            Err(_e) => Ok(None),
+        }
+    }
+
+    fn write(&self, path: &Path) -> IOResult<()> {
+        let toml = toml::to_string(&self).unwrap();
+        fileman::write_atomic(path, toml)
+    }
+
+    // records the app directories in `dir`, a working copy or the store
+    fn of_dir(dir: &Path) -> Result<PublishedFiles, Error> {
+        let mut published = PublishedFiles::default();
+        if !dir.exists() {
+            return Ok(published);
+        }
+        for dir_name in fileman::get_dir_names_in_path(dir)? {
+            if dir_name == ".git" || dir_name == largefiles::OBJECTS_DIR_NAME {
+                continue;
+            }
+            let mut files: BTreeMap<String, String> = BTreeMap::new();
+            for entry in fs::read_dir(dir.join(&dir_name))? {
+                let path = entry?.path();
+                if !path.is_file() {
+                    continue;
+                }
+                let id = match largefiles::read_pointer(&path) {
+                    Some(pointer) => pointer.oid,
+                    None => Oid::hash_file(ObjectType::Blob, &path)?,
+                };
+                let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+                files.insert(file_name, id.to_string());
+            }
+            published.apps.insert(dir_name, files);
+        }
+        Ok(published)
+    }
+
+    fn id(&self, app: &str, file: &str) -> Option<&String> {
+        self.apps.get(app)?.get(file)
+    }
+}
+
+// the files of the store which were changed since the revision it is based on, by app
+#[derive(Debug, Default)]
+struct Changes {
+    // changed only locally, they are pushed
+    local: BTreeMap<String, Vec<String>>,
+    // changed in the fetched revision as well, their apps are left alone
+    conflicts: BTreeMap<String, Vec<String>>,
+}
+
+impl Changes {
+    // compares the store and the fetched revision to `base`, the revision the store is
+    // based on. Without a base, files only the store has are local changes and files
+    // which differ are conflicts
+    fn between(
+        base: Option<&PublishedFiles>,
+        stored: &PublishedFiles,
+        fetched: &PublishedFiles,
+    ) -> Changes {
+        let mut changes = Changes::default();
+        let mut sides = vec![stored, fetched];
+        sides.extend(base);
+        let app_names: BTreeSet<&String> = sides.iter().flat_map(|side| side.apps.keys()).collect();
+        for app in app_names {
+            let file_names: BTreeSet<&String> = sides
+                .iter()
+                .filter_map(|side| side.apps.get(app))
+                .flat_map(|files| files.keys())
+                .collect();
+            for file in file_names {
+                let (local_id, fetched_id) = (stored.id(app, file), fetched.id(app, file));
+                if local_id == fetched_id {
+                    continue;
+                }
+                let (local, remote) = match base {
+                    Some(base) => {
+                        let base_id = base.id(app, file);
+                        (local_id != base_id, fetched_id != base_id)
+                    }
+                    None => (local_id.is_some(), fetched_id.is_some()),
+                };
+                let changed = match (local, remote) {
+                    (true, true) => &mut changes.conflicts,
+                    (true, false) => &mut changes.local,
+                    (false, _) => continue,
+                };
+                changed.entry(app.clone()).or_default().push(file.clone());
+            }
+        }
+        changes
+    }
+
+    // whether `file` of `app` was changed locally
+    fn contains(&self, app: &str, file: &str) -> bool {
+        [&self.local, &self.conflicts]
+            .iter()
+            .filter_map(|changed| changed.get(app))
+            .any(|files| files.iter().any(|f| f == file))
+    }
+}
+
+// copies of everything a sync changes locally, to undo it if a step fails
+struct Snapshot {
+    path: PathBuf,
+    store_path: PathBuf,
+    store_backup_path: PathBuf,
+    // installed files and their copies, None if a file was not installed
+    installed: Vec<(PathBuf, Option<PathBuf>)>,
+    // the files recording what the store is based on, and their content
+    records: Vec<(PathBuf, Option<String>)>,
+}
+
+impl Snapshot {
+    fn take(
+        path: PathBuf,
+        store: &Store,
+        apps: &[App],
+        records: &[&Path],
+    ) -> Result<Snapshot, Error> {
+        let store_backup_path = path.join("store");
+        fs::create_dir_all(&store_backup_path)?;
+        if store.path().exists() {
+            backend::copy_tree(store.path(), &store_backup_path)?;
+        }
+        let mut installed: Vec<(PathBuf, Option<PathBuf>)> = Vec::new();
+        for app in apps {
+            for file in app.file_names.iter() {
+                let installed_path = app.config_path.join(file);
+                if !installed_path.exists() {
+                    installed.push((installed_path, None));
+                    continue;
+                }
+                let backup_path = path.join("installed").join(&app.name).join(file);
+                fs::create_dir_all(backup_path.parent().unwrap())?;
+                fs::copy(&installed_path, &backup_path)?;
+                installed.push((installed_path, Some(backup_path)));
+            }
+        }
+        Ok(Snapshot {
+            store_path: store.path().to_path_buf(),
+            store_backup_path,
+            installed,
+            records: records
+                .iter()
+                .map(|record| (record.to_path_buf(), fs::read_to_string(record).ok()))
+                .collect(),
+            path,
+        })
+    }
+
+    fn restore(&self) -> Result<(), Error> {
+        if self.store_path.exists() {
+            fs::remove_dir_all(&self.store_path)?;
+        }
+        fs::create_dir_all(&self.store_path)?;
+        backend::copy_tree(&self.store_backup_path, &self.store_path)?;
+        for (installed_path, backup_path) in self.installed.iter() {
+            match backup_path {
+                Some(backup_path) => fileman::copy_atomic(backup_path, installed_path)?,
+                None if installed_path.exists() => fs::remove_file(installed_path)?,
+                None => {}
+            }
+        }
+        for (record_path, content) in self.records.iter() {
+            match content {
+                Some(content) => fileman::write_atomic(record_path, content)?,
+                None if record_path.exists() => fs::remove_file(record_path)?,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{DirectoryBackend, GitBackend};
+    use crate::testing::{init_upstream, set_home, sign_commits, ssh_key, with_home};
+
+    // one of the machines sharing an upstream
+    struct Machine {
+        home: PathBuf,
+        url: String,
+        signers: Option<AllowedSigners>,
+    }
+
+    impl Machine {
+        fn new(root: &Path, name: &str, upstream: &Path) -> Machine {
+            Machine {
+                home: root.join(name),
+                url: upstream.to_str().unwrap().to_string(),
+                signers: None,
+            }
+        }
+
+        // only pulls commits signed with one of `ssh_keys`
+        fn verifying(mut self, ssh_keys: &[&str]) -> Machine {
+            self.signers = Some(AllowedSigners {
+                fingerprints: Vec::new(),
+                ssh_keys: ssh_keys.iter().map(|key| key.to_string()).collect(),
+            });
+            self
+        }
+
+        fn engine(&self) -> SyncEngine {
+            set_home(&self.home);
+            let backend = GitBackend::new(&self.url, "upstream").with_signers(self.signers.clone());
+            let mut engine = SyncEngine::with(Box::new(backend), Store::default());
+            engine.allowed_signers = self.signers.clone();
+            engine
+        }
+
+        fn save(&self, content: &str) {
+            set_home(&self.home);
+            let app_path = Store::default().app_path("kitty");
+            fs::create_dir_all(&app_path).unwrap();
+            fs::write(app_path.join("kitty.conf"), content).unwrap();
+        }
+    }
+
+    fn apps() -> Apps {
+        let mut apps = Apps::empty();
+        let file_names = vec!["kitty.conf".to_string()];
+        apps.items.push(App::new(
+            "kitty".to_string(),
+            ".config/kitty".to_string(),
+            file_names,
+        ));
+        apps
+    }
+
+    #[test]
+    fn apps_removed_from_the_store_are_removed_upstream() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let mut apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+            a.save("kitty");
+            let bash_path = Store::default().app_path("bash");
+            fs::create_dir_all(&bash_path).unwrap();
+            fs::write(bash_path.join(".bashrc"), "bash").unwrap();
+            let file_names = vec![".bashrc".to_string()];
+            apps.items
+                .push(App::new("bash".to_string(), String::new(), file_names));
+            a.engine().push(&apps, "both").unwrap();
+
+            fs::remove_dir_all(Store::default().app_path("kitty")).unwrap();
+            apps.items.retain(|app| app.name != "kitty");
+            a.engine().push(&apps, "forget kitty").unwrap();
+
+            let repo = git2::Repository::open_bare(&upstream).unwrap();
+            let tree = repo.revparse_single("main^{tree}").unwrap();
+            let tree = tree.as_tree().unwrap();
+            assert!(tree.get_name("bash").is_some());
+            assert!(tree.get_name("kitty").is_none());
+        })
+    }
+
+    #[test]
+    fn pending_commits_follow_the_upstream_to_its_new_url() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let apps = apps();
+            let mut a = Machine::new(root, "a", &upstream);
+            a.save("base");
+            a.engine().push(&apps, "base").unwrap();
+
+            // the upstream moves while a has a commit waiting for it
+            let moved = root.join("moved.git");
+            backend::copy_tree(&upstream, &moved).unwrap();
+            fs::remove_dir_all(&upstream).unwrap();
+            a.save("offline");
+            assert_eq!(a.engine().push(&apps, "offline").unwrap().pending.len(), 1);
+
+            a.url = moved.to_str().unwrap().to_string();
+            let report = a.engine().push(&apps, "offline").unwrap();
+            assert!(report.deferred.is_none());
+            assert!(report.pending.is_empty());
+            let repo = git2::Repository::open_bare(&moved).unwrap();
+            let head = repo.revparse_single("main").unwrap();
+            assert_eq!(head.as_commit().unwrap().summary(), Some("offline"));
+        })
+    }
+
+    #[test]
+    fn conflicting_pushes_are_returned_as_conflicts() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+            let b = Machine::new(root, "b", &upstream);
+
+            a.save("base");
+            assert!(a.engine().push(&apps, "base").unwrap().deferred.is_none());
+            b.engine().pull(&apps).unwrap();
+
+            // b saves while the upstream can't be reached
+            let offline = root.join("offline.git");
+            fs::rename(&upstream, &offline).unwrap();
+            b.save("b");
+            let report = b.engine().push(&apps, "b").unwrap();
+            assert!(report.deferred.unwrap().is_network());
+            assert_eq!(report.pending.len(), 1);
+            fs::rename(&offline, &upstream).unwrap();
+
+            a.save("a");
+            assert!(a.engine().push(&apps, "a").unwrap().deferred.is_none());
+            match b.engine().push(&apps, "b again") {
+                Err(Error::Conflict(_commit, files, _repo_path)) => {
+                    assert_eq!(files, vec!["kitty/kitty.conf".to_string()])
+                }
+                result => panic!("expected a conflict, got {:?}", result),
+            }
+        })
+    }
+
+    fn assert_unverified<T: std::fmt::Debug>(result: Result<T, Error>, revision: Option<&str>) {
+        match result {
+            Err(Error::Unverified(id, _reason)) => {
+                if let Some(revision) = revision {
+                    assert_eq!(id, revision);
+                }
+            }
+            result => panic!("expected an unverified commit, got {:?}", result),
+        }
+    }
+
+    fn upstream_head(upstream: &Path) -> String {
+        let repo = git2::Repository::open_bare(upstream).unwrap();
+        let head = repo.revparse_single("main").unwrap();
+        head.id().to_string()
+    }
+
+    #[test]
+    fn unsigned_heads_are_refused() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let (_key, public_key) = ssh_key(root, "allowed");
+            let apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+            let b = Machine::new(root, "b", &upstream).verifying(&[&public_key]);
+
+            a.save("unsigned");
+            let revision = a.engine().push(&apps, "unsigned").unwrap().revision;
+            assert_unverified(b.engine().pull(&apps), Some(&revision));
+            // nothing was checked out
+            assert!(!Store::default().app_path("kitty").exists());
+        })
+    }
+
+    #[test]
+    fn heads_signed_with_other_keys_are_refused() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let (_key, public_key) = ssh_key(root, "allowed");
+            let (other_key, _public_key) = ssh_key(root, "other");
+            let apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+            sign_commits(&a.home, &other_key);
+            let b = Machine::new(root, "b", &upstream).verifying(&[&public_key]);
+
+            a.save("signed by another key");
+            let revision = a.engine().push(&apps, "other key").unwrap().revision;
+            assert_unverified(b.engine().pull(&apps), Some(&revision));
+            assert!(!Store::default().app_path("kitty").exists());
+        })
+    }
+
+    #[test]
+    fn unsigned_commits_since_the_last_pull_are_refused() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let (key, public_key) = ssh_key(root, "allowed");
+            let apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+            sign_commits(&a.home, &key);
+            let intruder = Machine::new(root, "intruder", &upstream);
+            let b = Machine::new(root, "b", &upstream).verifying(&[&public_key]);
+
+            a.save("base");
+            a.engine().push(&apps, "base").unwrap();
+            b.engine().pull(&apps).unwrap();
+
+            // an unsigned commit hidden below a signed head
+            intruder.save("intruder");
+            let unsigned = intruder.engine().push(&apps, "unsigned").unwrap().revision;
+            a.save("signed");
+            a.engine().push(&apps, "signed").unwrap();
+            assert_unverified(b.engine().pull(&apps), Some(&unsigned));
+            let kitty = Store::default().app_path("kitty").join("kitty.conf");
+            assert_eq!(fs::read_to_string(kitty).unwrap(), "base");
+        })
+    }
+
+    #[test]
+    fn unsigned_commits_are_not_pushed_onto() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let (key, public_key) = ssh_key(root, "allowed");
+            let apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+            sign_commits(&a.home, &key);
+            let intruder = Machine::new(root, "intruder", &upstream);
+            let b = Machine::new(root, "b", &upstream).verifying(&[&public_key]);
+            sign_commits(&b.home, &key);
+
+            a.save("base");
+            a.engine().push(&apps, "base").unwrap();
+            b.engine().pull(&apps).unwrap();
+
+            intruder.save("intruder");
+            let unsigned = intruder.engine().push(&apps, "unsigned").unwrap().revision;
+            b.save("b");
+            assert_unverified(b.engine().push(&apps, "b"), Some(&unsigned));
+            assert_eq!(upstream_head(&upstream), unsigned);
+            // pulling afterwards doesn't accept what the push fetched
+            assert_unverified(b.engine().pull(&apps), Some(&unsigned));
+        })
+    }
+
+    #[test]
+    fn unsigned_revisions_are_not_restored() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let init = upstream_head(&upstream);
+            let (key, public_key) = ssh_key(root, "allowed");
+            let apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+            sign_commits(&a.home, &key);
+            let b = Machine::new(root, "b", &upstream).verifying(&[&public_key]);
+
+            a.save("base");
+            let signed = a.engine().push(&apps, "base").unwrap().revision;
+            b.engine().pull(&apps).unwrap();
+
+            let engine = b.engine();
+            assert!(engine.restore(&apps, &apps.items[0], &signed).is_ok());
+            assert_unverified(engine.restore(&apps, &apps.items[0], &init), Some(&init));
+        })
+    }
+
+    // kitty's files larger than 4 bytes are kept in the side store
+    fn apps_storing_large_files() -> Apps {
+        let mut apps = apps();
+        apps.items[0].max_file_size = Some(4);
+        apps.items[0].large_files = LargeFilePolicy::Store;
+        apps
+    }
+
+    #[test]
+    fn stored_large_files_are_pulled_with_their_objects() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let apps = apps_storing_large_files();
+            let a = Machine::new(root, "a", &upstream);
+            let b = Machine::new(root, "b", &upstream);
+
+            a.save("large file");
+            let report = a.engine().push(&apps, "large").unwrap();
+            assert_eq!(report.stored.len(), 1);
+            let oid = report.stored[0].oid.to_string();
+            let repo = git2::Repository::open_bare(&upstream).unwrap();
+            let tree = repo.revparse_single("main^{tree}").unwrap();
+            let tree = tree.as_tree().unwrap();
+            let object = Path::new(largefiles::OBJECTS_DIR_NAME).join(&oid);
+            assert!(tree.get_path(&object).is_ok());
+
+            let reports = b.engine().pull(&apps).unwrap();
+            assert!(reports.iter().all(|report| report.warnings.is_empty()));
+            let kitty = Store::default().app_path("kitty").join("kitty.conf");
+            assert_eq!(fs::read_to_string(kitty).unwrap(), "large file");
+            assert!(largefiles::object_store(&apps).join(&oid).exists());
+        })
+    }
+
+    #[test]
+    fn corrupt_objects_are_not_published() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let apps = apps_storing_large_files();
+            let a = Machine::new(root, "a", &upstream);
+
+            // the side store already holds something else under the file's id
+            a.save("large file");
+            let oid = Oid::hash_object(ObjectType::Blob, b"large file").unwrap();
+            let object = largefiles::object_store(&apps).join(oid.to_string());
+            fs::create_dir_all(object.parent().unwrap()).unwrap();
+            fs::write(&object, "other content").unwrap();
+            match a.engine().push(&apps, "large") {
+                Err(Error::CorruptObject(path)) => assert_eq!(path, object),
+                result => panic!("expected a corrupt object, got {:?}", result),
+            }
+        })
+    }
+
+    fn upstream_file(upstream: &Path, path: &str) -> Option<String> {
+        let repo = git2::Repository::open_bare(upstream).unwrap();
+        let tree = repo.revparse_single("main^{tree}").unwrap();
+        let entry = tree.as_tree().unwrap().get_path(Path::new(path)).ok()?;
+        let blob = entry.to_object(&repo).unwrap().peel_to_blob().unwrap();
+        Some(String::from_utf8(blob.content().to_vec()).unwrap())
+    }
+
+    fn save_bash(content: &str, apps: &mut Apps) {
+        let bash_path = Store::default().app_path("bash");
+        fs::create_dir_all(&bash_path).unwrap();
+        fs::write(bash_path.join(".bashrc"), content).unwrap();
+        if apps.items.iter().all(|app| app.name != "bash") {
+            let file_names = vec![".bashrc".to_string()];
+            apps.items
+                .push(App::new("bash".to_string(), String::new(), file_names));
+        }
+    }
+
+    #[test]
+    fn unpushed_saves_are_pushed_or_reported_as_conflicts() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let mut apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+            let b = Machine::new(root, "b", &upstream);
+
+            a.save("base");
+            set_home(&a.home);
+            save_bash("base", &mut apps);
+            a.engine().push(&apps, "base").unwrap();
+            b.engine().pull(&apps).unwrap();
+
+            // a saves both apps without pushing, b pushes a change to kitty
+            a.save("a");
+            set_home(&a.home);
+            save_bash("a", &mut apps);
+            b.save("b");
+            b.engine().push(&apps, "b").unwrap();
+
+            let report = a.engine().sync(&apps, &apps.items, "sync").unwrap();
+            assert_eq!(report.conflicts.len(), 1);
+            assert_eq!(report.conflicts[0].app, "kitty");
+            assert_eq!(report.conflicts[0].files, vec!["kitty.conf".to_string()]);
+            assert!(report.pushed.is_some());
+            let kitty = Store::default().app_path("kitty").join("kitty.conf");
+            assert_eq!(fs::read_to_string(kitty).unwrap(), "a");
+            assert!(!Path::new(&apps.items[0].config_path)
+                .join("kitty.conf")
+                .exists());
+            assert_eq!(upstream_file(&upstream, "kitty/kitty.conf").unwrap(), "b");
+            assert_eq!(upstream_file(&upstream, "bash/.bashrc").unwrap(), "a");
+        })
+    }
+
+    #[test]
+    fn first_sync_pushes_new_apps() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let mut apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+            let b = Machine::new(root, "b", &upstream);
+
+            a.save("a");
+            a.engine().push(&apps, "a").unwrap();
+
+            // b added an app, but never synced
+            set_home(&b.home);
+            save_bash("b", &mut apps);
+            let report = b.engine().sync(&apps, &apps.items, "sync").unwrap();
+            assert!(report.conflicts.is_empty());
+            assert!(report.pushed.is_some());
+            assert_eq!(upstream_file(&upstream, "bash/.bashrc").unwrap(), "b");
+            assert_eq!(upstream_file(&upstream, "kitty/kitty.conf").unwrap(), "a");
+            let kitty = Store::default().app_path("kitty").join("kitty.conf");
+            assert_eq!(fs::read_to_string(kitty).unwrap(), "a");
+        })
+    }
+
+    #[test]
+    fn pushes_to_an_empty_upstream() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            git2::Repository::init_bare(&upstream).unwrap();
+            let apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+            let b = Machine::new(root, "b", &upstream);
+
+            a.save("a");
+            let report = a.engine().sync(&apps, &apps.items, "a").unwrap();
+            assert!(report.pushed.is_some());
+            assert_eq!(upstream_file(&upstream, "kitty/kitty.conf").unwrap(), "a");
+
+            b.engine().sync(&apps, &apps.items, "b").unwrap();
+            let kitty = Store::default().app_path("kitty").join("kitty.conf");
+            assert_eq!(fs::read_to_string(kitty).unwrap(), "a");
+        })
+    }
+
+    #[test]
+    fn directories_are_only_published_to_when_empty_or_published_to_before() {
+        with_home(|root| {
+            let apps = apps();
+            let share = root.join("share");
+            fs::create_dir_all(&share).unwrap();
+            fs::write(share.join("notes.txt"), "notes").unwrap();
+            Machine::new(root, "a", &share).save("a");
+            let engine = || {
+                let backend = DirectoryBackend::new(share.clone());
+                SyncEngine::with(Box::new(backend), Store::default())
+            };
+
+            let e = engine().push(&apps, "a").unwrap_err();
+            assert!(matches!(e, Error::ForeignDirectory(_)), "{}", e);
+            assert_eq!(
+                fs::read_to_string(share.join("notes.txt")).unwrap(),
+                "notes"
+            );
+
+            fs::remove_file(share.join("notes.txt")).unwrap();
+            engine().push(&apps, "a").unwrap();
+            engine().push(&apps, "b").unwrap();
+            let kitty = share.join("kitty").join("kitty.conf");
+            assert_eq!(fs::read_to_string(kitty).unwrap(), "a");
+        })
+    }
+
+    #[test]
+    fn skipped_large_files_are_left_out_of_pushes() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let mut apps = apps();
+            apps.items[0].file_names.push("big.bin".to_string());
+            apps.items[0].max_file_size = Some(4);
+            let a = Machine::new(root, "a", &upstream);
+
+            set_home(&a.home);
+            let config_path = apps.items[0].config_path.clone();
+            fs::create_dir_all(&config_path).unwrap();
+            fs::write(config_path.join("kitty.conf"), "kit").unwrap();
+            fs::write(config_path.join("big.bin"), "large file").unwrap();
+            let report = a.engine().sync(&apps, &apps.items, "sync").unwrap();
+            assert_eq!(report.saved.len(), 1);
+            assert_eq!(report.saved[0].warnings.len(), 1);
+            assert!(report.pushed.is_some());
+            assert_eq!(upstream_file(&upstream, "kitty/kitty.conf").unwrap(), "kit");
+            assert!(upstream_file(&upstream, "kitty/big.bin").is_none());
+        })
+    }
+
+    #[test]
+    fn directories_without_an_app_are_pushed_as_they_are() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let apps = apps();
+            let mut b_apps = apps.clone();
+            let a = Machine::new(root, "a", &upstream);
+            let b = Machine::new(root, "b", &upstream);
+
+            b.save("b");
+            set_home(&b.home);
+            save_bash("b", &mut b_apps);
+            b.engine().push(&b_apps, "b").unwrap();
+
+            // a has no entry for bash
+            a.engine().pull(&apps).unwrap();
+            a.save("a");
+            a.engine().push(&apps, "a").unwrap();
+            assert_eq!(upstream_file(&upstream, "kitty/kitty.conf").unwrap(), "a");
+            assert_eq!(upstream_file(&upstream, "bash/.bashrc").unwrap(), "b");
+        })
+    }
+
+    #[test]
+    fn removed_apps_are_pushed_by_sync() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let mut apps = apps();
+            let a = Machine::new(root, "a", &upstream);
+
+            a.save("a");
+            set_home(&a.home);
+            save_bash("a", &mut apps);
+            a.engine().push(&apps, "both").unwrap();
+
+            Store::default().remove("bash").unwrap();
+            assert!(!Store::default().app_path("bash").exists());
+            apps.items.retain(|app| app.name != "bash");
+            let report = a.engine().sync(&apps, &apps.items, "remove bash").unwrap();
+            assert!(report.pushed.is_some());
+            assert!(upstream_file(&upstream, "bash/.bashrc").is_none());
+            assert_eq!(upstream_file(&upstream, "kitty/kitty.conf").unwrap(), "a");
+        })
+    }
+
+    // the temporary directories restores leave behind
+    fn restore_dirs() -> usize {
+        fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("setman-") && name.contains("restore")
+            })
+            .count()
+    }
+
+    #[test]
+    fn restores_take_large_files_from_the_revision() {
+        with_home(|root| {
+            let upstream = root.join("upstream.git");
+            init_upstream(&upstream);
+            let apps = apps_storing_large_files();
+            let a = Machine::new(root, "a", &upstream);
+            let b = Machine::new(root, "b", &upstream);
+
+            a.save("large file, first version");
+            let first = a.engine().push(&apps, "first").unwrap().revision;
+            a.save("large file, second version");
+            a.engine().push(&apps, "second").unwrap();
+
+            // b only has the object of the second version
+            b.engine().pull(&apps).unwrap();
+            let restore_dirs_before = restore_dirs();
+            let engine = b.engine();
+            let report = engine.restore(&apps, &apps.items[0], &first).unwrap();
+            assert!(report.warnings.is_empty());
+            let kitty = Store::default().app_path("kitty").join("kitty.conf");
+            assert_eq!(
+                fs::read_to_string(kitty).unwrap(),
+                "large file, first version"
+            );
+            assert!(engine.restore(&apps, &apps.items[0], "0000000").is_err());
+            assert_eq!(restore_dirs(), restore_dirs_before);
+        })
+    }
+}
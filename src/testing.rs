@@ -0,0 +1,97 @@
+// Copyright (c) 2021 Oskar Hellkvist <hellkvistoskar@protonmail.com>
+
+// SPDX-License-Identifier: BSD-2-Clause
+
+//! Helpers shared by the unit tests.
+
+use crate::paths::TempDir;
+
+use git2::{ConfigLevel, Repository, Signature};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+// every path setman uses is derived from $HOME, so tests changing it run one at a time
+static HOME_LOCK: Mutex<()> = Mutex::new(());
+
+/// Runs `f` with $HOME set to a fresh directory containing an empty setman directory.
+pub fn with_home<T>(f: impl FnOnce(&Path) -> T) -> T {
+    let _guard = HOME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new("setman-test-home");
+    fs::create_dir_all(home.path().join(".config/setman")).unwrap();
+    let home_path = home.path().canonicalize().unwrap();
+    let old_home = env::var_os("HOME");
+    env::set_var("HOME", &home_path);
+    let result = f(&home_path);
+    match old_home {
+        Some(old_home) => env::set_var("HOME", old_home),
+        None => env::remove_var("HOME"),
+    }
+    result
+}
+
+/// Points $HOME at `home`, a machine with its own setman directory and git identity.
+/// Only for use inside of `with_home`.
+pub fn set_home(home: &Path) {
+    fs::create_dir_all(home.join(".config/setman/settings")).unwrap();
+    let git_config = home.join(".gitconfig");
+    if !git_config.exists() {
+        fs::write(
+            &git_config,
+            "[user]\n\tname = setman\n\temail = setman@localhost\n",
+        )
+        .unwrap();
+    }
+    env::set_var("HOME", home);
+    // libgit2 looks up the global config in $HOME only once
+    unsafe { git2::opts::set_search_path(ConfigLevel::Global, home).unwrap() };
+}
+
+/// Creates a bare repository at `path` with an empty initial commit on main, like
+/// the upstreams setman is pointed at.
+pub fn init_upstream(path: &Path) {
+    let repo = Repository::init_bare(path).unwrap();
+    let signature = Signature::now("setman", "setman@localhost").unwrap();
+    let tree_id = repo.treebuilder(None).unwrap().write().unwrap();
+    let tree = repo.find_tree(tree_id).unwrap();
+    repo.commit(
+        Some("refs/heads/main"),
+        &signature,
+        &signature,
+        "init",
+        &tree,
+        &[],
+    )
+    .unwrap();
+    repo.set_head("refs/heads/main").unwrap();
+}
+
+/// Generates a throwaway ssh key named `name` in `dir`, returns the path to the
+/// private key and the public key.
+pub fn ssh_key(dir: &Path, name: &str) -> (PathBuf, String) {
+    let key = dir.join(name);
+    let status = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", name, "-f"])
+        .arg(&key)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let public_key = fs::read_to_string(key.with_extension("pub")).unwrap();
+    (key, public_key.trim().to_string())
+}
+
+/// Makes the machine at `home` sign its commits with the ssh key at `key`.
+pub fn sign_commits(home: &Path, key: &Path) {
+    fs::create_dir_all(home).unwrap();
+    fs::write(
+        home.join(".gitconfig"),
+        format!(
+            "[user]\n\tname = setman\n\temail = setman@localhost\n\tsigningkey = {}\n\
+             [gpg]\n\tformat = ssh\n[commit]\n\tgpgsign = true\n",
+            key.display()
+        ),
+    )
+    .unwrap();
+}